@@ -0,0 +1,481 @@
+use super::socket::NetworkInterface;
+use crate::{
+    config::KademliaConfig,
+    error::Result,
+    helpers::ExpectLock,
+    identity::Identity,
+    protocol::{self, Protocol, ValueLookup},
+    shortlist::Shortlist,
+    state::State,
+    transport::{self, Transport},
+    types::{
+        distance::NodeDistance,
+        key::Key,
+        lookup::{LookupResult, LookupStats},
+        messages::{FindValueResult, Message, Request, Response, RpcRequest, WriteToken},
+        node::Node,
+    },
+};
+
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{sync::mpsc, task::JoinSet, time};
+
+#[derive(Clone)]
+pub struct Kademlia {
+    protocol: Arc<Protocol>,
+    rpc: Arc<NetworkInterface>,
+}
+
+impl Kademlia {
+    pub async fn new(addr: SocketAddr, identity: Identity) -> Result<Self> {
+        Self::with_config(KademliaConfig::builder().bind_addr(addr).build(), identity).await
+    }
+
+    /// Must be called within tokio runtime, requests are answered on spawned tasks.
+    /// Messages go over UDP, or over TCP as well if enabled in config
+    pub async fn with_config(config: KademliaConfig, identity: Identity) -> Result<Self> {
+        let transport = transport::bind(&config)?;
+        Self::start(config, identity, transport).await
+    }
+
+    /// Same as [`crate::Kademlia::with_transport`], transport is read on a thread
+    /// and written to on blocking tasks
+    pub async fn with_transport(
+        config: KademliaConfig,
+        identity: Identity,
+        transport: impl Transport,
+    ) -> Result<Self> {
+        Self::start(config, identity, Arc::new(transport)).await
+    }
+
+    async fn start(
+        config: KademliaConfig,
+        identity: Identity,
+        transport: Arc<dyn Transport>,
+    ) -> Result<Self> {
+        let rpc = NetworkInterface::new(&config, identity.clone(), transport).await?;
+        let protocol = Protocol::new(config, identity, rpc.node())?;
+
+        let rpc = Arc::new(rpc);
+        let (rpc_sender, mut rpc_receiver) = mpsc::unbounded_channel();
+        rpc.clone().spawn(rpc_sender);
+
+        let kademlia = Self {
+            protocol: Arc::new(protocol),
+            rpc,
+        };
+
+        let protocol = kademlia.clone();
+        tokio::spawn(async move {
+            while let Some(request) = rpc_receiver.recv().await {
+                let k = protocol.clone();
+                tokio::spawn(async move { k.respond(request).await });
+            }
+        });
+
+        let protocol = kademlia.clone();
+        tokio::spawn(async move {
+            loop {
+                time::sleep(protocol.protocol.config.maintenance_interval()).await;
+                protocol.refresh_buckets().await;
+                protocol.maintain_records().await;
+            }
+        });
+
+        Ok(kademlia)
+    }
+
+    async fn respond(&self, request: RpcRequest) {
+        let (token, from) = (request.token, request.from);
+        let (response, least_recent) = self.protocol.answer(request);
+
+        let sent = self
+            .rpc
+            .send_msg(token, Message::Response(response), from)
+            .await;
+
+        if let Err(err) = sent {
+            error!("Error responding to {}: {}", from, err);
+        }
+
+        // Liveness check is done after responding since it waits for network
+        self.evict_if_unresponsive(least_recent).await;
+    }
+
+    /// Same as [`crate::Kademlia`], routing table is never locked across an await
+    async fn evict_if_unresponsive(&self, least_recent: Option<Node>) {
+        if let Some(node) = least_recent {
+            // failed ping removes node and promotes a replacement, boxed since ping updates routes
+            let _ = Box::pin(self.ping(node)).await;
+            self.protocol.pinged(&node);
+        }
+    }
+
+    /// Looks up random key in range of every bucket that had no lookup for refresh interval
+    async fn refresh_buckets(&self) {
+        for (index, key) in self.protocol.refresh_keys() {
+            debug!("Refreshing bucket {}", index);
+            if let Err(err) = self.lookup_nodes(&key).await {
+                warn!("Error refreshing bucket {}: {}", index, err);
+            }
+        }
+    }
+
+    /// Same as [`crate::Kademlia::save_state`], file is written on a blocking task
+    pub async fn save_state(&self, path: impl AsRef<Path>) -> Result<()> {
+        let (state, path) = (self.protocol.state(), path.as_ref().to_owned());
+
+        tokio::task::spawn_blocking(move || state.save(&path))
+            .await
+            .expect("Error saving state")
+    }
+
+    /// Same as [`crate::Kademlia::load_state`], pings of a batch run as separate tasks
+    pub async fn load_state(path: impl AsRef<Path>, config: KademliaConfig) -> Result<Self> {
+        let path = path.as_ref().to_owned();
+        let State { secret_key, peers } = tokio::task::spawn_blocking(move || State::load(&path))
+            .await
+            .expect("Error loading state")?;
+
+        let kademlia = Self::with_config(config, Identity::from_secret_key(secret_key)).await?;
+
+        let mut restored = Vec::with_capacity(peers.len());
+        for batch in peers.chunks(kademlia.protocol.config.alpha) {
+            let mut pings = JoinSet::new();
+            for &(node, last_seen) in batch {
+                let protocol = kademlia.clone();
+                pings.spawn(async move { (node, last_seen, protocol.ping(node).await) });
+            }
+
+            while let Some(joined) = pings.join_next().await {
+                if let Ok((node, last_seen, Ok(()))) = joined {
+                    restored.push((node, last_seen));
+                }
+            }
+        }
+
+        kademlia.protocol.restore(restored);
+
+        Ok(kademlia)
+    }
+
+    /// Saves state to file every snapshot interval
+    pub fn start_snapshots(&self, path: impl Into<PathBuf>) {
+        let path = path.into();
+        let protocol = self.clone();
+
+        tokio::spawn(async move {
+            loop {
+                time::sleep(protocol.protocol.config.snapshot_interval).await;
+                if let Err(err) = protocol.save_state(&path).await {
+                    error!("Error saving state to {}: {}", path.display(), err);
+                }
+            }
+        });
+    }
+
+    pub async fn bootstrap(&self, node: Node) -> Result<()> {
+        // node is added once it responds, its puzzle nonce might not be known yet
+        self.ping(node).await?;
+        self.lookup_nodes(&self.protocol.node.id).await?;
+        Ok(())
+    }
+
+    pub fn node(&self) -> &Node {
+        &self.protocol.node
+    }
+
+    pub fn identity(&self) -> &Identity {
+        &self.protocol.identity
+    }
+
+    /// Number of responses dropped because they didn't come from the node request was sent to
+    pub fn rejected_responses(&self) -> u64 {
+        self.rpc.rejected_responses()
+    }
+
+    pub fn get_all_know_nodes(&self) -> Vec<Node> {
+        self.protocol.known_nodes()
+    }
+
+    /// Same as [`crate::Kademlia`] query, waits for response without blocking a thread
+    async fn query<T>(
+        &self,
+        request: Request,
+        dst: Node,
+        answer: impl FnOnce(Response) -> Option<T>,
+    ) -> Result<T> {
+        let received = self.rpc.request(request, dst).await;
+        let result = received.and_then(|received| protocol::expect(received, answer));
+
+        let (result, least_recent) = self.protocol.record_liveness(dst, result);
+        self.evict_if_unresponsive(least_recent).await;
+        result
+    }
+
+    pub async fn ping(&self, dst: Node) -> Result<()> {
+        self.query(Request::Ping, dst, |response| {
+            matches!(response, Response::Pong).then_some(())
+        })
+        .await
+    }
+
+    pub async fn find_node(&self, dst: Node, id: Key) -> Result<Vec<Node>> {
+        self.query(Request::FindNode(id), dst, |response| match response {
+            Response::FindNode(nodes) => Some(nodes),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Stores value on dst, it expires after ttl or after record ttl of dst if that is shorter
+    pub async fn store(&self, dst: Node, key: Key, value: Vec<u8>, ttl: Duration) -> Result<()> {
+        self.store_request(dst, Request::Store(key, value, ttl, false))
+            .await
+    }
+
+    /// Stores copy of value on dst that is not republished
+    async fn cache(&self, dst: Node, key: Key, value: Vec<u8>, ttl: Duration) -> Result<()> {
+        self.store_request(dst, Request::Store(key, value, ttl, true))
+            .await
+    }
+
+    async fn store_request(&self, dst: Node, request: Request) -> Result<()> {
+        self.query(request, dst, |response| {
+            matches!(response, Response::Store).then_some(())
+        })
+        .await
+    }
+
+    pub async fn find_value(&self, dst: Node, key: Key) -> Result<FindValueResult> {
+        self.query(Request::FindValue(key), dst, |response| match response {
+            Response::FindValue(found, result) if found == key => Some(result),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Same as [`crate::Kademlia::add_provider`]
+    pub async fn add_provider(&self, dst: Node, key: Key, token: Vec<u8>) -> Result<()> {
+        let request = Request::AddProvider(key, WriteToken::Issued(token));
+        self.query(request, dst, |response| {
+            matches!(response, Response::AddProvider).then_some(())
+        })
+        .await
+    }
+
+    /// Providers of key known to dst with write token for announcing to dst
+    pub async fn find_providers(&self, dst: Node, key: Key) -> Result<(Vec<Node>, Vec<u8>)> {
+        self.query(Request::GetProviders(key), dst, |response| match response {
+            Response::GetProviders(providers, _, token) => Some((providers, token)),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Same as [`crate::Kademlia::put`]
+    pub async fn put(&self, key: Key, value: Vec<u8>) -> Result<usize> {
+        let key = self.protocol.key(&key);
+        self.protocol.publish(key, value.clone());
+        self.replicate(key, value, self.protocol.config.record_ttl)
+            .await
+    }
+
+    /// Same as [`crate::Kademlia::unpublish`]
+    pub fn unpublish(&self, key: &Key) {
+        self.protocol.unpublish(&self.protocol.key(key));
+    }
+
+    async fn replicate(&self, key: Key, value: Vec<u8>, ttl: Duration) -> Result<usize> {
+        let mut stores = JoinSet::new();
+        for NodeDistance { node, .. } in self.lookup_nodes(&key).await? {
+            let (protocol, value) = (self.clone(), value.clone());
+            stores.spawn(async move { protocol.store(node, key, value, ttl).await });
+        }
+
+        Ok(count_ok(stores).await)
+    }
+
+    /// Same as [`crate::Kademlia::start_providing`]
+    pub async fn start_providing(&self, key: Key) -> Result<usize> {
+        let key = self.protocol.key(&key);
+        self.protocol.provide(key);
+        self.announce(key).await
+    }
+
+    /// Same as [`crate::Kademlia::stop_providing`]
+    pub fn stop_providing(&self, key: &Key) {
+        self.protocol.stop_providing(&self.protocol.key(key));
+    }
+
+    /// Every node is asked for write token before announcing
+    async fn announce(&self, key: Key) -> Result<usize> {
+        let mut announces = JoinSet::new();
+        for NodeDistance { node, .. } in self.lookup_nodes(&key).await? {
+            let protocol = self.clone();
+            announces.spawn(async move {
+                let (_, token) = protocol.find_providers(node, key).await?;
+                protocol.add_provider(node, key, token).await
+            });
+        }
+
+        Ok(count_ok(announces).await)
+    }
+
+    /// Providers of key known locally and to the k closest nodes to key
+    pub async fn get_providers(&self, key: &Key) -> Result<Vec<Node>> {
+        let key = &self.protocol.key(key);
+        let mut providers = self.protocol.get_providers(key);
+
+        let mut queries = JoinSet::new();
+        for NodeDistance { node, .. } in self.lookup_nodes(key).await? {
+            let (protocol, key) = (self.clone(), *key);
+            queries.spawn(async move { protocol.find_providers(node, key).await });
+        }
+
+        while let Some(joined) = queries.join_next().await {
+            if let Ok(Ok((found, _))) = joined {
+                providers.extend(found);
+            }
+        }
+
+        Ok(protocol::unique_providers(providers))
+    }
+
+    /// Same as [`crate::Kademlia`], records are maintained one after another
+    async fn maintain_records(&self) {
+        let (replicate, provide) = self.protocol.due_records();
+
+        for (key, value, ttl) in replicate {
+            if let Err(err) = self.replicate(key, value, ttl).await {
+                warn!("Error republishing {}: {}", key, err);
+            }
+        }
+
+        for key in provide {
+            if let Err(err) = self.announce(key).await {
+                warn!("Error announcing provider of {}: {}", key, err);
+            }
+        }
+    }
+
+    /// Same as [`crate::Kademlia::get`], queries of a round run as separate tasks
+    pub async fn get(&self, key: &Key) -> Result<Option<Vec<u8>>> {
+        let key = &self.protocol.key(key);
+        if let Some(value) = self.protocol.get_value(key) {
+            return Ok(Some(value));
+        }
+
+        let mut lookup = ValueLookup::new(&self.protocol, key);
+
+        loop {
+            let batch = lookup.next_round();
+            if batch.is_empty() {
+                break;
+            }
+
+            let mut queries = JoinSet::new();
+            for node in batch {
+                let (protocol, key) = (self.clone(), *key);
+                queries.spawn(async move { (node, protocol.find_value(node, key).await) });
+            }
+
+            while let Some(joined) = queries.join_next().await {
+                if let Ok((node, result)) = joined {
+                    lookup.record(&self.protocol, &node, result);
+                }
+            }
+        }
+
+        if let Some((node, value, ttl)) = lookup.cache_at() {
+            let (protocol, key) = (self.clone(), *key);
+            tokio::spawn(async move {
+                if let Err(err) = protocol.cache(node, key, value, ttl).await {
+                    warn!("Error caching {} at {}: {}", key, node.addr, err);
+                }
+            });
+        }
+
+        lookup.finish()
+    }
+
+    /// k closest nodes to id, see [`Kademlia::lookup`]
+    pub async fn lookup_nodes(&self, id: &Key) -> Result<Vec<NodeDistance>> {
+        Ok(self
+            .lookup(id)
+            .await?
+            .nodes
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    /// Same as [`crate::Kademlia::lookup`], paths run as separate tasks
+    pub async fn lookup(&self, id: &Key) -> Result<LookupResult> {
+        let id = &self.protocol.key(id);
+        let queried = Arc::new(Mutex::new(HashSet::new()));
+
+        let mut lookups = JoinSet::new();
+        for (path, shortlist) in self.protocol.lookup_paths(id).into_iter().enumerate() {
+            let (protocol, queried, id) = (self.clone(), queried.clone(), *id);
+
+            lookups
+                .spawn(async move { (path, protocol.lookup_path(id, shortlist, &queried).await) });
+        }
+
+        let mut found = vec![];
+        while let Some(joined) = lookups.join_next().await {
+            found.extend(joined.ok());
+        }
+
+        self.protocol.merge_paths(found)
+    }
+
+    /// Runs lookup rounds until the k closest nodes of the path were queried
+    /// without finding closer node. Nodes queried by other paths are skipped
+    async fn lookup_path(
+        &self,
+        id: Key,
+        mut shortlist: Shortlist,
+        queried: &Mutex<HashSet<Key>>,
+    ) -> (Vec<NodeDistance>, LookupStats) {
+        loop {
+            let batch = shortlist.next_round(self.protocol.config.alpha, |node| {
+                queried.expect_lock().insert(node.id)
+            });
+
+            if batch.is_empty() {
+                return shortlist.finish();
+            }
+
+            let mut queries = JoinSet::new();
+            for node in batch {
+                let protocol = self.clone();
+                queries.spawn(async move { (node, protocol.find_node(node, id).await) });
+            }
+
+            while let Some(joined) = queries.join_next().await {
+                if let Ok((node, result)) = joined {
+                    self.protocol.record_nodes(&mut shortlist, &node, result);
+                }
+            }
+        }
+    }
+}
+
+/// Number of tasks that succeeded
+async fn count_ok(mut tasks: JoinSet<Result<()>>) -> usize {
+    let mut succeeded = 0;
+    while let Some(joined) = tasks.join_next().await {
+        if matches!(joined, Ok(Ok(()))) {
+            succeeded += 1;
+        }
+    }
+    succeeded
+}
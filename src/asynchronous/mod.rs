@@ -0,0 +1,6 @@
+//! Tokio based [`Kademlia`] that runs on tasks instead of a thread per request
+
+mod kademlia;
+mod socket;
+
+pub use kademlia::Kademlia;
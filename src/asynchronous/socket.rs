@@ -0,0 +1,120 @@
+use crate::{
+    config::KademliaConfig,
+    error::{Error, Result},
+    identity::Identity,
+    socket::{sign_within_mtu, PendingRequests},
+    transport::Transport,
+    types::{
+        key::KeySpace,
+        messages::{Message, Request, Response, RpcRequest},
+        node::Node,
+    },
+};
+
+use std::{net::SocketAddr, sync::Arc, thread, time::Duration};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task, time,
+};
+
+pub struct NetworkInterface {
+    transport: Arc<dyn Transport>,
+    pending: PendingRequests<oneshot::Sender<(Response, Node)>>,
+    identity: Identity,
+    node: Node,
+    request_timeout: Duration,
+    allow_unsigned: bool,
+    key_space: KeySpace,
+    mtu: usize,
+}
+
+impl NetworkInterface {
+    pub async fn new(
+        config: &KademliaConfig,
+        identity: Identity,
+        transport: Arc<dyn Transport>,
+    ) -> Result<Self> {
+        let external = config.addresses.external;
+        let id = config.key_space.node_id(&identity.public_key());
+
+        // dynamic puzzle can take seconds, it is solved off the runtime workers
+        let difficulty = config.puzzle_difficulty;
+        let nonce = task::spawn_blocking(move || difficulty.solve_dynamic(&id))
+            .await
+            .expect("Error solving puzzle")?;
+        let node = Node::new(external.unwrap_or(transport.local_addr()?), id).with_nonce(nonce);
+
+        Ok(Self {
+            transport,
+            pending: PendingRequests::default(),
+            identity,
+            node,
+            request_timeout: config.request_timeout,
+            allow_unsigned: config.allow_unsigned,
+            key_space: config.key_space,
+            mtu: config.mtu,
+        })
+    }
+
+    /// Reads messages on a thread since transports block, requests are forwarded
+    /// to sender and responses complete pending requests
+    pub fn spawn(self: Arc<Self>, sender: mpsc::UnboundedSender<RpcRequest>) {
+        thread::spawn(move || {
+            self.pending.receive(
+                &*self.transport,
+                self.allow_unsigned,
+                self.key_space,
+                |request| sender.send(request).is_ok(),
+                |waiting, response| {
+                    let _ = waiting.send(response);
+                },
+            )
+        });
+    }
+
+    pub fn node(&self) -> Node {
+        self.node
+    }
+
+    pub fn rejected_responses(&self) -> u64 {
+        self.pending.rejected()
+    }
+
+    /// Signs message as this node and sends it on a blocking task,
+    /// node lists are shortened to fit in one datagram
+    pub async fn send_msg(
+        &self,
+        token: u128,
+        message: Message,
+        destination: SocketAddr,
+    ) -> Result<()> {
+        let msg = sign_within_mtu(token, self.node, message, &self.identity, self.mtu)?;
+        let transport = self.transport.clone();
+
+        task::spawn_blocking(move || transport.send(&msg, destination))
+            .await
+            .expect("Error sending message")
+    }
+
+    /// Sends request and waits for response at most request timeout,
+    /// response is returned with the node that sent it
+    pub async fn request(&self, request: Request, destination: Node) -> Result<(Response, Node)> {
+        let (sender, receiver) = oneshot::channel();
+
+        let token = self.pending.insert(destination, sender);
+
+        let sent = self.send_msg(token, Message::Request(request), destination.addr);
+
+        let result = match sent.await {
+            Ok(()) => match time::timeout(self.request_timeout, receiver).await {
+                Ok(Ok(received)) => Ok(received),
+                Ok(Err(_)) => Err(Error::Disconnected),
+                Err(_) => Err(Error::Timeout),
+            },
+            Err(err) => Err(err),
+        };
+
+        self.pending.remove(&token);
+        result
+    }
+}
@@ -0,0 +1,211 @@
+//! Bencoding used by BitTorrent, integers, byte strings, lists and dictionaries
+//! with byte string keys in sorted order
+
+use crate::error::{Error, Result};
+use std::collections::BTreeMap;
+
+/// Deeper nesting is rejected instead of recursing further
+const MAX_DEPTH: usize = 32;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Int(i64),
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+    Dict(BTreeMap<Vec<u8>, Value>),
+}
+
+impl Value {
+    /// Dictionary from string keys
+    pub fn dict<const N: usize>(entries: [(&str, Value); N]) -> Self {
+        Value::Dict(
+            entries
+                .into_iter()
+                .map(|(key, value)| (key.as_bytes().to_vec(), value))
+                .collect(),
+        )
+    }
+
+    pub fn bytes(bytes: impl Into<Vec<u8>>) -> Self {
+        Value::Bytes(bytes.into())
+    }
+
+    /// Does nothing if value is not a dictionary
+    pub fn insert(&mut self, key: &str, value: Value) {
+        if let Value::Dict(dict) = self {
+            dict.insert(key.as_bytes().to_vec(), value);
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Dict(dict) => dict.get(key.as_bytes()),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![];
+        self.write(&mut out);
+        out
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::Int(int) => out.extend(format!("i{int}e").into_bytes()),
+            Value::Bytes(bytes) => write_bytes(bytes, out),
+            Value::List(list) => {
+                out.push(b'l');
+                list.iter().for_each(|value| value.write(out));
+                out.push(b'e');
+            }
+            Value::Dict(dict) => {
+                out.push(b'd');
+                for (key, value) in dict {
+                    write_bytes(key, out);
+                    value.write(out);
+                }
+                out.push(b'e');
+            }
+        }
+    }
+
+    /// Whole input has to be a single value
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut parser = Parser { bytes, pos: 0 };
+        let value = parser.value(0)?;
+
+        if parser.pos != bytes.len() {
+            return Err(invalid("trailing bytes"));
+        }
+        Ok(value)
+    }
+}
+
+fn write_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    out.extend(bytes.len().to_string().into_bytes());
+    out.push(b':');
+    out.extend_from_slice(bytes);
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::Codec(format!("invalid bencode, {reason}"))
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn value(&mut self, depth: usize) -> Result<Value> {
+        if depth > MAX_DEPTH {
+            return Err(invalid("nested too deep"));
+        }
+
+        match self.peek()? {
+            b'i' => {
+                self.pos += 1;
+                let int = self.until(b'e')?;
+                let int = int.parse().map_err(|_| invalid("bad integer"))?;
+                Ok(Value::Int(int))
+            }
+            b'l' => {
+                self.pos += 1;
+                let mut list = vec![];
+                while self.peek()? != b'e' {
+                    list.push(self.value(depth + 1)?);
+                }
+                self.pos += 1;
+                Ok(Value::List(list))
+            }
+            b'd' => {
+                self.pos += 1;
+                let mut dict = BTreeMap::new();
+                while self.peek()? != b'e' {
+                    let key = self.byte_string()?;
+                    let value = self.value(depth + 1)?;
+                    dict.insert(key, value);
+                }
+                self.pos += 1;
+                Ok(Value::Dict(dict))
+            }
+            b'0'..=b'9' => Ok(Value::Bytes(self.byte_string()?)),
+            _ => Err(invalid("unexpected byte")),
+        }
+    }
+
+    fn byte_string(&mut self) -> Result<Vec<u8>> {
+        let len: usize = self
+            .until(b':')?
+            .parse()
+            .map_err(|_| invalid("bad string length"))?;
+
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| invalid("string longer than input"))?;
+
+        let bytes = self.bytes[self.pos..end].to_vec();
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// Text up to delimiter, delimiter is consumed
+    fn until(&mut self, delimiter: u8) -> Result<String> {
+        let rest = &self.bytes[self.pos..];
+        let len = rest
+            .iter()
+            .position(|byte| *byte == delimiter)
+            .ok_or_else(|| invalid("unterminated value"))?;
+
+        let text = String::from_utf8(rest[..len].to_vec()).map_err(|_| invalid("not ascii"))?;
+        self.pos += len + 1;
+        Ok(text)
+    }
+
+    fn peek(&self) -> Result<u8> {
+        self.bytes
+            .get(self.pos)
+            .copied()
+            .ok_or_else(|| invalid("unexpected end"))
+    }
+}
+
+#[test]
+fn bencode_test() {
+    let value = Value::dict([
+        ("t", Value::bytes("aa")),
+        (
+            "a",
+            Value::dict([("id", Value::bytes("abcdefghij0123456789"))]),
+        ),
+        ("l", Value::List(vec![Value::Int(-3), Value::bytes("")])),
+    ]);
+
+    let encoded = value.encode();
+    assert_eq!(
+        encoded,
+        b"d1:ad2:id20:abcdefghij0123456789e1:lli-3e0:e1:t2:aae".to_vec()
+    );
+    assert_eq!(Value::decode(&encoded).unwrap(), value);
+
+    assert!(Value::decode(b"4:abc").is_err());
+    assert!(Value::decode(b"i1ei2e").is_err());
+    assert!(Value::decode(&[b'l'; 100]).is_err());
+}
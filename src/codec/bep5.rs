@@ -0,0 +1,523 @@
+//! BitTorrent Mainline DHT wire format from BEP 5, bencoded KRPC messages.
+//!
+//! PING, FIND_NODE, GET_PROVIDERS and ADD_PROVIDER are sent as `ping`, `find_node`,
+//! `get_peers` and `announce_peer` queries, STORE and FIND_VALUE have no equivalent
+//! and fail to encode. IPv6 nodes travel in `nodes6` as described in BEP 32.
+//! `get_peers` for a key without known peers is answered with the closest nodes.
+//! Rejections are sent as KRPC errors, and received errors are rejections of the
+//! query they answer.
+//!
+//! Node ids and info hashes are 160 bits, so nodes using this codec need
+//! [`KeySpace::Mainline`](crate::KeySpace::Mainline) and keys that don't fit fail to encode.
+//! KRPC carries neither signatures nor puzzle nonces, decoded messages are unsigned
+//! so nodes using this codec need [`allow_unsigned`](crate::KademliaConfigBuilder::allow_unsigned).
+//! Peers returned by `get_peers` have no node id, and announced peers are recorded
+//! at the address the announce came from regardless of its `port` argument
+
+use super::{bencode::Value, Codec};
+use crate::{
+    error::{Error, Result},
+    helpers::ExpectLock,
+    types::{
+        key::Key,
+        messages::{Message, Request, Response, RpcMessage, WriteToken},
+        node::Node,
+    },
+    KEY_SIZE,
+};
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Length of node ids and info hashes
+const ID_SIZE: usize = 20;
+
+/// Queries without response are forgotten after this long
+const PENDING_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// KRPC "Protocol Error", sent for refused requests
+const PROTOCOL_ERROR: i64 = 203;
+
+/// Query a response answers, KRPC responses don't name it
+#[derive(Clone, Copy, Debug)]
+enum Query {
+    Ping,
+    FindNode,
+    GetPeers,
+    AnnouncePeer,
+}
+
+#[derive(Debug, Default)]
+pub struct Bep5Codec {
+    /// Sent queries by transaction
+    pending: Mutex<HashMap<u128, (Query, Instant)>>,
+}
+
+impl Bep5Codec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn remember(&self, token: u128, query: Query) {
+        let mut pending = self.pending.expect_lock();
+        pending.retain(|_, (_, sent)| sent.elapsed() < PENDING_TIMEOUT);
+        pending.insert(token, (query, Instant::now()));
+    }
+
+    fn encode_request(&self, token: u128, source: &Node, request: &Request) -> Result<Value> {
+        let id = Value::bytes(id_bytes(&source.id)?);
+        let (query, name, args) = match request {
+            Request::Ping => (Query::Ping, "ping", Value::dict([("id", id)])),
+            Request::FindNode(target) => (
+                Query::FindNode,
+                "find_node",
+                Value::dict([("id", id), ("target", Value::bytes(id_bytes(target)?))]),
+            ),
+            Request::GetProviders(key) => (
+                Query::GetPeers,
+                "get_peers",
+                Value::dict([("id", id), ("info_hash", Value::bytes(id_bytes(key)?))]),
+            ),
+            Request::AddProvider(key, WriteToken::Issued(write_token)) => (
+                Query::AnnouncePeer,
+                "announce_peer",
+                Value::dict([
+                    ("id", id),
+                    ("implied_port", Value::Int(1)),
+                    ("info_hash", Value::bytes(id_bytes(key)?)),
+                    ("port", Value::Int(source.addr.port().into())),
+                    ("token", Value::bytes(write_token.clone())),
+                ]),
+            ),
+            Request::AddProvider(_, WriteToken::Transport) => {
+                return Err(unsupported("ADD_PROVIDER without write token"))
+            }
+            Request::Store(..) => return Err(unsupported("STORE")),
+            Request::FindValue(_) => return Err(unsupported("FIND_VALUE")),
+        };
+
+        self.remember(token, query);
+
+        Ok(Value::dict([
+            ("t", Value::bytes(transaction_id(token))),
+            ("y", Value::bytes("q")),
+            ("q", Value::bytes(name)),
+            ("a", args),
+        ]))
+    }
+}
+
+impl Codec for Bep5Codec {
+    fn encode(&self, msg: &RpcMessage) -> Result<Vec<u8>> {
+        let transaction = ("t", Value::bytes(transaction_id(msg.token)));
+
+        let value = match &msg.message {
+            Message::Request(request) => self.encode_request(msg.token, &msg.source, request)?,
+            Message::Response(Response::Rejected(reason)) => Value::dict([
+                transaction,
+                ("y", Value::bytes("e")),
+                (
+                    "e",
+                    Value::List(vec![
+                        Value::Int(PROTOCOL_ERROR),
+                        Value::bytes(reason.as_bytes()),
+                    ]),
+                ),
+            ]),
+            Message::Response(response) => Value::dict([
+                transaction,
+                ("y", Value::bytes("r")),
+                ("r", encode_response(&msg.source, response)?),
+            ]),
+        };
+
+        Ok(value.encode())
+    }
+
+    fn decode(&self, bytes: &[u8], from: SocketAddr) -> Result<RpcMessage> {
+        let value = Value::decode(bytes)?;
+        let token = token(field(&value, "t")?)?;
+
+        match field(&value, "y")? {
+            b"q" => {
+                let args = value.get("a").ok_or_else(|| missing("a"))?;
+                let source = Node::new(from, key(args, "id")?);
+
+                let request = match field(&value, "q")? {
+                    b"ping" => Request::Ping,
+                    b"find_node" => Request::FindNode(key(args, "target")?),
+                    b"get_peers" => Request::GetProviders(key(args, "info_hash")?),
+                    b"announce_peer" => Request::AddProvider(
+                        key(args, "info_hash")?,
+                        WriteToken::Issued(field(args, "token")?.to_vec()),
+                    ),
+                    query => {
+                        let query = String::from_utf8_lossy(query);
+                        return Err(Error::Codec(format!("unknown query {query}")));
+                    }
+                };
+
+                Ok(RpcMessage::unsigned(
+                    token,
+                    source,
+                    Message::Request(request),
+                ))
+            }
+            b"r" => {
+                let (query, _) =
+                    self.pending.expect_lock().remove(&token).ok_or_else(|| {
+                        Error::Codec("response to unknown transaction".to_owned())
+                    })?;
+
+                let result = value.get("r").ok_or_else(|| missing("r"))?;
+                let source = Node::new(from, key(result, "id")?);
+
+                let response = match query {
+                    Query::Ping => Response::Pong,
+                    Query::FindNode => Response::FindNode(nodes(result)?),
+                    Query::GetPeers => {
+                        let write_token = result.get("token").and_then(Value::as_bytes);
+                        Response::GetProviders(
+                            peers(result)?,
+                            nodes(result)?,
+                            write_token.unwrap_or_default().to_vec(),
+                        )
+                    }
+                    Query::AnnouncePeer => Response::AddProvider,
+                };
+
+                Ok(RpcMessage::unsigned(
+                    token,
+                    source,
+                    Message::Response(response),
+                ))
+            }
+            b"e" => {
+                self.pending
+                    .expect_lock()
+                    .remove(&token)
+                    .ok_or_else(|| Error::Codec("error for unknown transaction".to_owned()))?;
+
+                let reason = value
+                    .get("e")
+                    .and_then(Value::as_list)
+                    .and_then(|error| error.get(1))
+                    .and_then(Value::as_bytes)
+                    .unwrap_or_default();
+                let reason = String::from_utf8_lossy(reason).into_owned();
+
+                // errors don't carry the sender id, requester matches them by address
+                let source = Node::new(from, Key([0; KEY_SIZE]));
+                Ok(RpcMessage::unsigned(
+                    token,
+                    source,
+                    Message::Response(Response::Rejected(reason)),
+                ))
+            }
+            _ => Err(Error::Codec("unknown message type".to_owned())),
+        }
+    }
+}
+
+fn encode_response(source: &Node, response: &Response) -> Result<Value> {
+    let mut result = Value::dict([("id", Value::bytes(id_bytes(&source.id)?))]);
+
+    match response {
+        Response::Pong | Response::AddProvider => {}
+        Response::FindNode(found) => {
+            let (nodes, nodes6) = compact_nodes(found);
+            result.insert("nodes", Value::Bytes(nodes));
+            if !nodes6.is_empty() {
+                result.insert("nodes6", Value::Bytes(nodes6));
+            }
+        }
+        Response::GetProviders(providers, closest, write_token) => {
+            result.insert("token", Value::bytes(write_token.clone()));
+            if providers.is_empty() {
+                let (nodes, nodes6) = compact_nodes(closest);
+                result.insert("nodes", Value::Bytes(nodes));
+                if !nodes6.is_empty() {
+                    result.insert("nodes6", Value::Bytes(nodes6));
+                }
+            } else {
+                let values = providers
+                    .iter()
+                    .map(|provider| Value::Bytes(compact_addr(provider.addr)))
+                    .collect();
+                result.insert("values", Value::List(values));
+            }
+        }
+        Response::Store => return Err(unsupported("STORE")),
+        Response::FindValue(..) => return Err(unsupported("FIND_VALUE")),
+        Response::Incompatible(..) => return Err(unsupported("Version rejection")),
+        Response::Rejected(_) => unreachable!("rejections are sent as KRPC errors"),
+    }
+
+    Ok(result)
+}
+
+/// Compact node info of IPv4 and IPv6 nodes, id followed by compact address.
+/// Nodes with ids longer than 160 bits are left out
+fn compact_nodes(found: &[Node]) -> (Vec<u8>, Vec<u8>) {
+    let mut nodes = vec![];
+    let mut nodes6 = vec![];
+
+    for node in found {
+        let Ok(id) = id_bytes(&node.id) else {
+            continue;
+        };
+
+        let out = if node.addr.is_ipv4() {
+            &mut nodes
+        } else {
+            &mut nodes6
+        };
+        out.extend_from_slice(id);
+        out.extend(compact_addr(node.addr));
+    }
+
+    (nodes, nodes6)
+}
+
+/// Nodes from `nodes` and `nodes6`
+fn nodes(result: &Value) -> Result<Vec<Node>> {
+    let mut found = vec![];
+
+    for (name, addr_len) in [("nodes", 6), ("nodes6", 18)] {
+        let Some(compact) = result.get(name).and_then(Value::as_bytes) else {
+            continue;
+        };
+
+        let chunk_len = ID_SIZE + addr_len;
+        if compact.len() % chunk_len != 0 {
+            return Err(Error::Codec(format!(
+                "{name} length is not a multiple of {chunk_len}"
+            )));
+        }
+
+        for chunk in compact.chunks(chunk_len) {
+            let id = to_key(&chunk[..ID_SIZE])?;
+            let addr = parse_addr(&chunk[ID_SIZE..])?;
+            found.push(Node::new(addr, id));
+        }
+    }
+
+    Ok(found)
+}
+
+/// Peers from `values`, they have no node id
+fn peers(result: &Value) -> Result<Vec<Node>> {
+    let Some(values) = result.get("values").and_then(Value::as_list) else {
+        return Ok(vec![]);
+    };
+
+    values
+        .iter()
+        .map(|value| {
+            let compact = value.as_bytes().ok_or_else(|| missing("values"))?;
+            Ok(Node::new(parse_addr(compact)?, Key([0; KEY_SIZE])))
+        })
+        .collect()
+}
+
+/// IP address in network byte order followed by port
+fn compact_addr(addr: SocketAddr) -> Vec<u8> {
+    let mut compact = match addr.ip() {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    compact.extend(addr.port().to_be_bytes());
+    compact
+}
+
+fn parse_addr(compact: &[u8]) -> Result<SocketAddr> {
+    let ip = match compact.len() {
+        6 => IpAddr::from(<[u8; 4]>::try_from(&compact[..4]).expect("length was checked")),
+        18 => IpAddr::from(<[u8; 16]>::try_from(&compact[..16]).expect("length was checked")),
+        len => return Err(Error::Codec(format!("compact address of {len} bytes"))),
+    };
+    let port = u16::from_be_bytes([compact[compact.len() - 2], compact[compact.len() - 1]]);
+
+    Ok(SocketAddr::new(ip, port))
+}
+
+/// Transaction ids of other implementations are usually 2 bytes. Length of ids
+/// shorter than a token is kept in its highest byte, so responses echo them unchanged
+fn transaction_id(token: u128) -> Vec<u8> {
+    let bytes = token.to_be_bytes();
+    let len = bytes[0] as usize;
+
+    if (1..16).contains(&len) && bytes[1..16 - len].iter().all(|byte| *byte == 0) {
+        bytes[16 - len..].to_vec()
+    } else {
+        bytes.to_vec()
+    }
+}
+
+fn token(transaction_id: &[u8]) -> Result<u128> {
+    let mut bytes = [0; 16];
+
+    match transaction_id.len() {
+        16 => bytes.copy_from_slice(transaction_id),
+        len @ 1..=15 => {
+            bytes[0] = len as u8;
+            bytes[16 - len..].copy_from_slice(transaction_id);
+        }
+        len => return Err(Error::Codec(format!("transaction id of {len} bytes"))),
+    }
+
+    Ok(u128::from_be_bytes(bytes))
+}
+
+fn field<'a>(value: &'a Value, name: &str) -> Result<&'a [u8]> {
+    value
+        .get(name)
+        .and_then(Value::as_bytes)
+        .ok_or_else(|| missing(name))
+}
+
+fn key(value: &Value, name: &str) -> Result<Key> {
+    to_key(field(value, name)?)
+}
+
+/// 160-bit id as key, trailing bytes are zero
+fn to_key(bytes: &[u8]) -> Result<Key> {
+    if bytes.len() != ID_SIZE {
+        return Err(Error::Codec(format!("key of {} bytes", bytes.len())));
+    }
+
+    let mut key = Key([0; KEY_SIZE]);
+    key.0[..ID_SIZE].copy_from_slice(bytes);
+    Ok(key)
+}
+
+/// Leading 160 bits of key, keys with other bits set don't fit
+fn id_bytes(key: &Key) -> Result<&[u8]> {
+    let (id, rest) = key.0.split_at(ID_SIZE);
+    if rest.iter().any(|byte| *byte != 0) {
+        return Err(Error::Codec(format!("key {key} is longer than 160 bits")));
+    }
+    Ok(id)
+}
+
+fn missing(name: &str) -> Error {
+    Error::Codec(format!("missing {name}"))
+}
+
+fn unsupported(rpc: &str) -> Error {
+    Error::Codec(format!("{rpc} is not part of BEP 5"))
+}
+
+#[test]
+fn bep5_codec_test() {
+    let codec = Bep5Codec::new();
+    let from = SocketAddr::from(([127, 0, 0, 1], 6881));
+    let querying = to_key(b"abcdefghij0123456789").unwrap();
+    let queried = to_key(b"mnopqrstuvwxyz123456").unwrap();
+
+    // examples from BEP 5
+    let ping = codec
+        .decode(
+            b"d1:ad2:id20:abcdefghij0123456789e1:q4:ping1:t2:aa1:y1:qe",
+            from,
+        )
+        .unwrap();
+    assert!(matches!(ping.message, Message::Request(Request::Ping)));
+    assert_eq!(ping.source, Node::new(from, querying));
+    assert!(!ping.is_signed());
+
+    let pong = RpcMessage::unsigned(
+        ping.token,
+        Node::new(from, queried),
+        Message::Response(Response::Pong),
+    );
+    assert_eq!(
+        codec.encode(&pong).unwrap(),
+        b"d1:rd2:id20:mnopqrstuvwxyz123456e1:t2:aa1:y1:re".to_vec()
+    );
+
+    // response is decoded according to the query it answers
+    let ipv6 = SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], 6882));
+    let found = vec![Node::new(from, queried), Node::new(ipv6, querying)];
+    let find_node = RpcMessage::unsigned(
+        7,
+        Node::new(from, querying),
+        Message::Request(Request::FindNode(querying)),
+    );
+    codec.encode(&find_node).unwrap();
+
+    let response = RpcMessage::unsigned(
+        7,
+        Node::new(from, queried),
+        Message::Response(Response::FindNode(found.clone())),
+    );
+    let response = codec
+        .decode(&codec.encode(&response).unwrap(), from)
+        .unwrap();
+    assert!(
+        matches!(response.message, Message::Response(Response::FindNode(decoded)) if decoded == found)
+    );
+
+    let unknown = codec.decode(b"d1:rd2:id20:mnopqrstuvwxyz123456e1:t2:aa1:y1:re", from);
+    assert!(matches!(unknown, Err(Error::Codec(_))));
+
+    // without peers get_peers returns closest nodes
+    let get_peers = RpcMessage::unsigned(
+        9,
+        Node::new(from, querying),
+        Message::Request(Request::GetProviders(querying)),
+    );
+    codec.encode(&get_peers).unwrap();
+
+    let response = RpcMessage::unsigned(
+        9,
+        Node::new(from, queried),
+        Message::Response(Response::GetProviders(
+            vec![],
+            found.clone(),
+            b"token".to_vec(),
+        )),
+    );
+    let encoded = codec.encode(&response).unwrap();
+    assert!(!encoded.windows(8).any(|window| window == b"6:values"));
+    let response = codec.decode(&encoded, from).unwrap();
+    assert!(matches!(
+        response.message,
+        Message::Response(Response::GetProviders(providers, closest, token))
+            if providers.is_empty() && closest == found && token == b"token"
+    ));
+
+    // error completes the pending query
+    let announce = Request::AddProvider(querying, WriteToken::Issued(b"aoeusnth".to_vec()));
+    let announce = RpcMessage::unsigned(
+        ping.token,
+        Node::new(from, querying),
+        Message::Request(announce),
+    );
+    codec.encode(&announce).unwrap();
+
+    let error = codec
+        .decode(b"d1:eli203e9:Bad tokene1:t2:aa1:y1:ee", from)
+        .unwrap();
+    assert_eq!(error.token, ping.token);
+    assert_eq!(error.source.addr, from);
+    assert!(
+        matches!(error.message, Message::Response(Response::Rejected(reason)) if reason == "Bad token")
+    );
+
+    let unknown = codec.decode(b"d1:eli203e9:Bad tokene1:t2:aa1:y1:ee", from);
+    assert!(matches!(unknown, Err(Error::Codec(_))));
+
+    let store = Request::Store(querying, vec![], Duration::ZERO, false);
+    let store = RpcMessage::unsigned(8, Node::new(from, querying), Message::Request(store));
+    assert!(codec.encode(&store).is_err());
+
+    // native 256-bit ids don't fit
+    let native = Node::new(from, Key::new("native".to_owned()));
+    let ping = RpcMessage::unsigned(9, native, Message::Request(Request::Ping));
+    assert!(codec.encode(&ping).is_err());
+}
@@ -0,0 +1,704 @@
+//! libp2p Kademlia wire format (`/ipfs/kad/1.0.0`), protobuf messages prefixed
+//! with their varint length, exchanged on streams of libp2p connections.
+//!
+//! libp2p messages name neither their sender nor a transaction, connection
+//! handshake identifies the peer and every request is written on a stream of its
+//! own that carries the response back. So the transport running the streams tells
+//! requests from responses: frames read from streams peers opened are decoded with
+//! [`Libp2pCodec::decode_request`], and frames read from the stream a request was
+//! written on with [`Libp2pCodec::decode_response`]. Frames go on the stream as
+//! [`Libp2pCodec::encode`] returns them, without framing of their own around them.
+//!
+//! Keys of libp2p are byte strings that sit at their sha2-256 in the key space. Key
+//! received from a peer is the sha2-256 of its bytes, and the bytes are remembered so
+//! requests for the key carry them again: node lookups send the peer id of the target,
+//! requests for values and providers the record key or multihash the key was learned
+//! from or registered with [`Libp2pCodec::key`]. So a value stored under
+//! `codec.key(b"name")` is the record with key `name` to libp2p peers. Keys whose
+//! bytes aren't known can't be sent, their preimage can't be recovered from the hash.
+//!
+//! Peer ids are multihashes of public keys and node ids are sha2-256 of peer ids, as
+//! the Kademlia keys of libp2p peers are. Nodes using the codec need
+//! [`KeySpace::Libp2p`] so their own id is derived the same way from their identity.
+//! Only nodes with known peer id are sent to peers.
+//! Addresses are sent as `/ip4` or `/ip6` multiaddrs with `/tcp` port.
+//! Messages are unsigned, write tokens and record ttl don't travel: ADD_PROVIDER
+//! arrives with [`WriteToken::Transport`] since the stream proved the peer, and
+//! PUT_VALUE asks for the longest ttl the receiver keeps
+
+use crate::{
+    error::{Error, Result},
+    helpers::ExpectLock,
+    identity::{self, Identity},
+    types::{
+        key::{Key, KeySpace},
+        messages::{FindValueResult, Message, Request, Response, RpcMessage, WriteToken},
+        node::Node,
+    },
+};
+
+use ed25519_dalek::VerifyingKey;
+use prost::{
+    encoding::{decode_varint, encode_varint},
+    Message as _,
+};
+use rand::{rngs::OsRng, Rng};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::Read,
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+    time::Duration,
+};
+
+/// Key bytes that are remembered, oldest ones are forgotten to make room
+const MAX_PREIMAGES: usize = 4096;
+
+/// Multiaddr protocol codes
+const IP4: u64 = 4;
+const IP6: u64 = 41;
+const TCP: u64 = 6;
+const UDP: u64 = 273;
+
+/// Messages of `dht.proto` from go-libp2p-kad-dht
+mod pb {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Record {
+        #[prost(bytes = "vec", tag = "1")]
+        pub key: Vec<u8>,
+        #[prost(bytes = "vec", tag = "2")]
+        pub value: Vec<u8>,
+        #[prost(string, tag = "5")]
+        pub time_received: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Peer {
+        #[prost(bytes = "vec", tag = "1")]
+        pub id: Vec<u8>,
+        #[prost(bytes = "vec", repeated, tag = "2")]
+        pub addrs: Vec<Vec<u8>>,
+        #[prost(enumeration = "ConnectionType", tag = "3")]
+        pub connection: i32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Message {
+        #[prost(enumeration = "MessageType", tag = "1")]
+        pub r#type: i32,
+        #[prost(int32, tag = "10")]
+        pub cluster_level_raw: i32,
+        #[prost(bytes = "vec", tag = "2")]
+        pub key: Vec<u8>,
+        #[prost(message, optional, tag = "3")]
+        pub record: Option<Record>,
+        #[prost(message, repeated, tag = "8")]
+        pub closer_peers: Vec<Peer>,
+        #[prost(message, repeated, tag = "9")]
+        pub provider_peers: Vec<Peer>,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum MessageType {
+        PutValue = 0,
+        GetValue = 1,
+        AddProvider = 2,
+        GetProviders = 3,
+        FindNode = 4,
+        Ping = 5,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum ConnectionType {
+        NotConnected = 0,
+        Connected = 1,
+        CanConnect = 2,
+        CannotConnect = 3,
+    }
+}
+
+use pb::MessageType;
+
+/// Bytes of keys, peer ids of nodes among them, by the key they hash to, oldest first
+#[derive(Default)]
+struct Preimages {
+    by_key: HashMap<Key, Vec<u8>>,
+    order: VecDeque<Key>,
+}
+
+pub struct Libp2pCodec {
+    node_id: Key,
+    peer_id: Vec<u8>,
+    preimages: Mutex<Preimages>,
+}
+
+impl Libp2pCodec {
+    /// Codec of node with identity, its peer id is derived from the public key.
+    /// The node has to be in [`KeySpace::Libp2p`] so its id is the key of the peer id
+    pub fn new(identity: &Identity) -> Self {
+        Self {
+            node_id: KeySpace::Libp2p.node_id(&identity.public_key()),
+            peer_id: Self::peer_id(&identity.public_key()),
+            preimages: Mutex::default(),
+        }
+    }
+
+    /// Peer id of ed25519 public key, identity multihash of the protobuf encoded key
+    pub fn peer_id(public_key: &VerifyingKey) -> Vec<u8> {
+        identity::peer_id(public_key)
+    }
+
+    /// Key of libp2p key bytes such as a record key, requests for it carry these bytes
+    pub fn key(&self, bytes: &[u8]) -> Key {
+        self.learn(bytes)
+    }
+
+    /// Frame of request or response, protobuf message with its varint length
+    pub fn encode(&self, msg: &RpcMessage) -> Result<Vec<u8>> {
+        let message = match &msg.message {
+            Message::Request(request) => self.encode_request(&msg.source, request)?,
+            Message::Response(response) => self.encode_response(response)?,
+        };
+
+        Ok(message.encode_length_delimited_to_vec())
+    }
+
+    /// Request read from a stream the peer opened, peer id is the one
+    /// connection handshake verified
+    pub fn decode_request(
+        &self,
+        frame: &[u8],
+        peer_id: &[u8],
+        from: SocketAddr,
+    ) -> Result<RpcMessage> {
+        let (kind, message) = decode_frame(frame)?;
+        let source = Node::new(from, self.learn(peer_id));
+
+        let request = self.decode_request_message(kind, message)?;
+        Ok(RpcMessage::unsigned(
+            OsRng.gen(),
+            source,
+            Message::Request(request),
+        ))
+    }
+
+    /// Response read from the stream request was written on, it gets the token of request
+    pub fn decode_response(
+        &self,
+        frame: &[u8],
+        request: &RpcMessage,
+        peer_id: &[u8],
+        from: SocketAddr,
+    ) -> Result<RpcMessage> {
+        let Message::Request(sent) = &request.message else {
+            return Err(Error::Codec("response to a response".to_owned()));
+        };
+
+        let (kind, message) = decode_frame(frame)?;
+        if kind != request_type(sent) {
+            return Err(Error::Codec(format!(
+                "{kind:?} response to {:?} request",
+                request_type(sent)
+            )));
+        }
+
+        let source = Node::new(from, self.learn(peer_id));
+        let response = self.decode_response_message(kind, message, sent)?;
+        Ok(RpcMessage::unsigned(
+            request.token,
+            source,
+            Message::Response(response),
+        ))
+    }
+
+    /// Reads one frame from a stream, the frame is returned with its length prefix
+    /// and frames longer than `max_size` are refused
+    pub fn read_frame(reader: &mut impl Read, max_size: usize) -> Result<Vec<u8>> {
+        let mut frame = vec![];
+
+        loop {
+            let mut byte = [0];
+            reader.read_exact(&mut byte)?;
+            frame.push(byte[0]);
+
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+            if frame.len() == 10 {
+                return Err(Error::Codec("length prefix too long".to_owned()));
+            }
+        }
+
+        let len = prost::decode_length_delimiter(&frame[..]).map_err(codec_error)?;
+        if len > max_size {
+            return Err(Error::Codec(format!("frame of {len} bytes")));
+        }
+
+        let start = frame.len();
+        frame.resize(start + len, 0);
+        reader.read_exact(&mut frame[start..])?;
+        Ok(frame)
+    }
+
+    /// Key of key bytes or node id of peer id, remembered so the key can be sent
+    /// in requests and the node to other peers
+    fn learn(&self, bytes: &[u8]) -> Key {
+        if bytes == self.peer_id {
+            return self.node_id;
+        }
+
+        let key = Key::from_hash(bytes);
+        let mut preimages = self.preimages.expect_lock();
+        if preimages.by_key.contains_key(&key) {
+            return key;
+        }
+
+        if preimages.order.len() == MAX_PREIMAGES {
+            if let Some(oldest) = preimages.order.pop_front() {
+                preimages.by_key.remove(&oldest);
+            }
+        }
+        preimages.by_key.insert(key, bytes.to_vec());
+        preimages.order.push_back(key);
+        key
+    }
+
+    /// Bytes key was learned from, peer id for node ids
+    fn preimage(&self, key: &Key) -> Option<Vec<u8>> {
+        if *key == self.node_id {
+            return Some(self.peer_id.clone());
+        }
+
+        self.preimages.expect_lock().by_key.get(key).cloned()
+    }
+
+    fn encode_key(&self, key: &Key) -> Result<Vec<u8>> {
+        self.preimage(key)
+            .ok_or_else(|| Error::Codec(format!("no libp2p key of {key}")))
+    }
+
+    fn decode_key(&self, bytes: &[u8]) -> Result<Key> {
+        if bytes.is_empty() {
+            return Err(missing("key"));
+        }
+
+        Ok(self.learn(bytes))
+    }
+
+    /// Nodes with unknown peer id are left out
+    fn encode_peers(&self, nodes: &[Node]) -> Vec<pb::Peer> {
+        nodes
+            .iter()
+            .filter_map(|node| {
+                Some(pb::Peer {
+                    id: self.preimage(&node.id)?,
+                    addrs: vec![encode_multiaddr(node.addr)],
+                    connection: pb::ConnectionType::NotConnected.into(),
+                })
+            })
+            .collect()
+    }
+
+    /// Peers without an ip and port address are skipped
+    fn decode_peers(&self, peers: &[pb::Peer]) -> Vec<Node> {
+        peers
+            .iter()
+            .filter_map(|peer| {
+                let addr = peer.addrs.iter().find_map(|addr| decode_multiaddr(addr))?;
+                Some(Node::new(addr, self.learn(&peer.id)))
+            })
+            .collect()
+    }
+
+    fn encode_request(&self, source: &Node, request: &Request) -> Result<pb::Message> {
+        let kind = message(request_type(request));
+
+        let message = match request {
+            Request::Ping => kind,
+            Request::FindNode(key) | Request::FindValue(key) | Request::GetProviders(key) => {
+                pb::Message {
+                    key: self.encode_key(key)?,
+                    ..kind
+                }
+            }
+            Request::Store(key, value, ..) => pb::Message {
+                key: self.encode_key(key)?,
+                record: Some(pb::Record {
+                    key: self.encode_key(key)?,
+                    value: value.clone(),
+                    time_received: String::new(),
+                }),
+                ..kind
+            },
+            Request::AddProvider(key, _) => pb::Message {
+                key: self.encode_key(key)?,
+                provider_peers: self.encode_peers(std::slice::from_ref(source)),
+                ..kind
+            },
+        };
+
+        Ok(message)
+    }
+
+    fn decode_request_message(&self, kind: MessageType, message: pb::Message) -> Result<Request> {
+        let request = match kind {
+            MessageType::Ping => Request::Ping,
+            MessageType::FindNode => Request::FindNode(self.decode_key(&message.key)?),
+            MessageType::PutValue => {
+                let record = message.record.ok_or_else(|| missing("record"))?;
+                let key = self.decode_key(&record.key)?;
+                Request::Store(key, record.value, Duration::MAX, false)
+            }
+            MessageType::GetValue => Request::FindValue(self.decode_key(&message.key)?),
+            MessageType::AddProvider => {
+                Request::AddProvider(self.decode_key(&message.key)?, WriteToken::Transport)
+            }
+            MessageType::GetProviders => Request::GetProviders(self.decode_key(&message.key)?),
+        };
+
+        Ok(request)
+    }
+
+    fn encode_response(&self, response: &Response) -> Result<pb::Message> {
+        let message = match response {
+            Response::Pong => message(MessageType::Ping),
+            Response::FindNode(nodes) => pb::Message {
+                closer_peers: self.encode_peers(nodes),
+                ..message(MessageType::FindNode)
+            },
+            Response::Store => message(MessageType::PutValue),
+            // peers check the record key against the key they asked for
+            Response::FindValue(key, FindValueResult::Value(value)) => pb::Message {
+                key: self.encode_key(key)?,
+                record: Some(pb::Record {
+                    key: self.encode_key(key)?,
+                    value: value.clone(),
+                    time_received: String::new(),
+                }),
+                ..message(MessageType::GetValue)
+            },
+            Response::FindValue(key, FindValueResult::Nodes(nodes)) => pb::Message {
+                key: self.encode_key(key)?,
+                closer_peers: self.encode_peers(nodes),
+                ..message(MessageType::GetValue)
+            },
+            Response::AddProvider => message(MessageType::AddProvider),
+            Response::GetProviders(providers, closest, _) => pb::Message {
+                provider_peers: self.encode_peers(providers),
+                closer_peers: self.encode_peers(closest),
+                ..message(MessageType::GetProviders)
+            },
+            Response::Rejected(reason) => {
+                return Err(Error::Codec(format!(
+                    "libp2p can't reject requests ({reason})"
+                )))
+            }
+            Response::Incompatible(..) => {
+                return Err(Error::Codec("libp2p can't reject requests".to_owned()))
+            }
+        };
+
+        Ok(message)
+    }
+
+    /// Value is returned under the key of its record, requester compares it with the
+    /// key it sent
+    fn decode_response_message(
+        &self,
+        kind: MessageType,
+        message: pb::Message,
+        sent: &Request,
+    ) -> Result<Response> {
+        let response = match (kind, sent) {
+            (MessageType::Ping, _) => Response::Pong,
+            (MessageType::FindNode, _) => {
+                Response::FindNode(self.decode_peers(&message.closer_peers))
+            }
+            (MessageType::PutValue, _) => Response::Store,
+            (MessageType::GetValue, Request::FindValue(key)) => match message.record {
+                Some(record) => Response::FindValue(
+                    self.decode_key(&record.key)?,
+                    FindValueResult::Value(record.value),
+                ),
+                None => Response::FindValue(
+                    *key,
+                    FindValueResult::Nodes(self.decode_peers(&message.closer_peers)),
+                ),
+            },
+            (MessageType::AddProvider, _) => Response::AddProvider,
+            (MessageType::GetProviders, _) => {
+                let providers = self.decode_peers(&message.provider_peers);
+                let closest = self.decode_peers(&message.closer_peers);
+                Response::GetProviders(providers, closest, vec![])
+            }
+            (MessageType::GetValue, _) => return Err(missing("GET_VALUE request")),
+        };
+
+        Ok(response)
+    }
+}
+
+/// Message of frame with its type, length prefix has to match the frame
+fn decode_frame(frame: &[u8]) -> Result<(MessageType, pb::Message)> {
+    let mut buf = frame;
+    let len = prost::decode_length_delimiter(&mut buf).map_err(codec_error)?;
+    if buf.len() != len {
+        return Err(Error::Codec(format!(
+            "frame of {len} bytes has {} bytes",
+            buf.len()
+        )));
+    }
+
+    let message = pb::Message::decode(buf).map_err(codec_error)?;
+    let kind = MessageType::try_from(message.r#type)
+        .map_err(|_| Error::Codec(format!("unknown message type {}", message.r#type)))?;
+
+    Ok((kind, message))
+}
+
+fn request_type(request: &Request) -> MessageType {
+    match request {
+        Request::Ping => MessageType::Ping,
+        Request::FindNode(_) => MessageType::FindNode,
+        Request::Store(..) => MessageType::PutValue,
+        Request::FindValue(_) => MessageType::GetValue,
+        Request::AddProvider(..) => MessageType::AddProvider,
+        Request::GetProviders(_) => MessageType::GetProviders,
+    }
+}
+
+fn message(kind: MessageType) -> pb::Message {
+    let mut message = pb::Message::default();
+    message.set_type(kind);
+    message
+}
+
+fn encode_multiaddr(addr: SocketAddr) -> Vec<u8> {
+    let mut multiaddr = vec![];
+
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            encode_varint(IP4, &mut multiaddr);
+            multiaddr.extend(ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            encode_varint(IP6, &mut multiaddr);
+            multiaddr.extend(ip.octets());
+        }
+    }
+
+    encode_varint(TCP, &mut multiaddr);
+    multiaddr.extend(addr.port().to_be_bytes());
+    multiaddr
+}
+
+/// First ip and port of multiaddr, `None` if it starts with other protocols
+fn decode_multiaddr(mut multiaddr: &[u8]) -> Option<SocketAddr> {
+    let ip = match decode_varint(&mut multiaddr).ok()? {
+        IP4 => {
+            let (ip, rest) = multiaddr.split_first_chunk::<4>()?;
+            multiaddr = rest;
+            IpAddr::from(*ip)
+        }
+        IP6 => {
+            let (ip, rest) = multiaddr.split_first_chunk::<16>()?;
+            multiaddr = rest;
+            IpAddr::from(*ip)
+        }
+        _ => return None,
+    };
+
+    match decode_varint(&mut multiaddr).ok()? {
+        TCP | UDP => {
+            let (port, _) = multiaddr.split_first_chunk::<2>()?;
+            Some(SocketAddr::new(ip, u16::from_be_bytes(*port)))
+        }
+        _ => None,
+    }
+}
+
+fn missing(name: &str) -> Error {
+    Error::Codec(format!("missing {name}"))
+}
+
+fn codec_error(err: impl std::fmt::Display) -> Error {
+    Error::Codec(err.to_string())
+}
+
+#[test]
+fn libp2p_codec_test() {
+    let identity = Identity::generate();
+    let codec = Libp2pCodec::new(&identity);
+    let id = KeySpace::Libp2p.node_id(&identity.public_key());
+    let local = Node::new(SocketAddr::from(([127, 0, 0, 1], 4002)), id);
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4001));
+
+    // Ed25519 key from the test vectors of the libp2p peer id spec
+    let secret_key = hex("7e0830617c4a7de83925dfb2694556b12936c477a0e1feb2e148ec9da60fee7d");
+    let peer_identity = Identity::from_secret_key(secret_key.try_into().unwrap());
+    let public_key = peer_identity.public_key();
+    let peer_id = Libp2pCodec::peer_id(&public_key);
+    assert_eq!(
+        peer_id,
+        hex("0024080112201ed1e8fae2c4a144b8be8fd4b47bf3d3b34b871c3cacf6010f0e42d474fce27e")
+    );
+
+    // its libp2p Kademlia key, sha2-256 of the peer id bytes as kbucket::Key of
+    // rust-libp2p hashes them, is the node id of a node with that identity
+    let kad_key: Key = "DFD53212A4BD2BEDA3EA8E82D08285370C70A70CFE9C588E28754B23C8033121"
+        .parse()
+        .unwrap();
+    assert_eq!(KeySpace::Libp2p.node_id(&public_key), kad_key);
+    assert_eq!(Libp2pCodec::new(&peer_identity).key(&peer_id), kad_key);
+    assert_eq!(codec.key(&peer_id), kad_key);
+    let peer_node = Node::new(peer, kad_key);
+
+    // PING on a stream the peer opened, answered on the same stream
+    let ping = codec
+        .decode_request(&[0x02, 0x08, 0x05], &peer_id, peer)
+        .unwrap();
+    assert!(matches!(ping.message, Message::Request(Request::Ping)));
+    assert_eq!(ping.source, peer_node);
+
+    let pong = RpcMessage::unsigned(ping.token, local, Message::Response(Response::Pong));
+    assert_eq!(codec.encode(&pong).unwrap(), vec![0x02, 0x08, 0x05]);
+
+    // FIND_NODE as go-libp2p-kad-dht sends it, key is the peer id looked up
+    let mut find_node = vec![0x2A, 0x08, 0x04, 0x12, 0x26];
+    find_node.extend(&peer_id);
+    let request = codec.decode_request(&find_node, &peer_id, peer).unwrap();
+    assert!(
+        matches!(request.message, Message::Request(Request::FindNode(target)) if target == peer_node.id)
+    );
+
+    // closer peer at /ip4/127.0.0.1/tcp/4001, only nodes with known peer id are sent
+    let unknown = Node::new(peer, Key::new("unknown".to_owned()));
+    let mut response = vec![0x36, 0x08, 0x04, 0x42, 0x32, 0x0A, 0x26];
+    response.extend(&peer_id);
+    response.extend([0x12, 0x08, 0x04, 127, 0, 0, 1, 0x06, 0x0F, 0xA1]);
+    let found = Response::FindNode(vec![peer_node, unknown]);
+    let found = RpcMessage::unsigned(request.token, local, Message::Response(found));
+    assert_eq!(codec.encode(&found).unwrap(), response);
+
+    // node lookup sends peer id of the target, response is read from the stream
+    // request was written on
+    let expected = find_node;
+    let find_node = Request::FindNode(peer_node.id);
+    let find_node = RpcMessage::unsigned(9, local, Message::Request(find_node));
+    assert_eq!(codec.encode(&find_node).unwrap(), expected);
+
+    let decoded = codec
+        .decode_response(&response, &find_node, &peer_id, peer)
+        .unwrap();
+    assert_eq!(decoded.token, 9);
+    assert_eq!(decoded.source, peer_node);
+    assert!(
+        matches!(decoded.message, Message::Response(Response::FindNode(nodes)) if nodes == vec![peer_node])
+    );
+    assert!(codec
+        .decode_response(&[0x02, 0x08, 0x05], &find_node, &peer_id, peer)
+        .is_err());
+
+    // keys are sent as the bytes they hash from, unknown bytes can't be sent
+    let target = codec.key(b"target");
+    assert_eq!(target, Key::new("target".to_owned()));
+    let unknown = Request::FindValue(Key::new("unknown".to_owned()));
+    let unknown = RpcMessage::unsigned(12, local, Message::Request(unknown));
+    assert!(matches!(codec.encode(&unknown), Err(Error::Codec(_))));
+
+    let get = RpcMessage::unsigned(11, local, Message::Request(Request::FindValue(target)));
+    assert_eq!(codec.encode(&get).unwrap(), b"\x0A\x08\x01\x12\x06target");
+    let received = codec
+        .decode_request(&codec.encode(&get).unwrap(), &peer_id, peer)
+        .unwrap();
+    assert!(matches!(received.message, Message::Request(Request::FindValue(key)) if key == target));
+
+    // this node is sent with the peer id of its identity
+    let announce = Request::AddProvider(target, WriteToken::Transport);
+    let announce = RpcMessage::unsigned(10, local, Message::Request(announce));
+    let own_peer_id = Libp2pCodec::peer_id(&identity.public_key());
+    let encoded = codec.encode(&announce).unwrap();
+    assert!(encoded
+        .windows(own_peer_id.len())
+        .any(|window| window == own_peer_id));
+
+    // round trip of a value, record carries its key
+    let value = FindValueResult::Value(b"value".to_vec());
+    let found = Response::FindValue(target, value);
+    let found = RpcMessage::unsigned(0, local, Message::Response(found));
+    let encoded = codec.encode(&found).unwrap();
+    let record = b"\x1A\x0F\x0A\x06target\x12\x05value";
+    assert!(encoded.windows(record.len()).any(|window| window == record));
+    let found = codec
+        .decode_response(&encoded, &get, &peer_id, peer)
+        .unwrap();
+    assert!(
+        matches!(found.message, Message::Response(Response::FindValue(key, FindValueResult::Value(value))) if key == target && value == b"value")
+    );
+
+    let mut stream = &[0x02, 0x08, 0x05, 0x02][..];
+    assert_eq!(
+        Libp2pCodec::read_frame(&mut stream, 16).unwrap(),
+        vec![0x02, 0x08, 0x05]
+    );
+    assert!(Libp2pCodec::read_frame(&mut stream, 16).is_err());
+}
+
+#[test]
+fn libp2p_add_provider_test() {
+    use crate::{
+        config::KademliaConfig, protocol::handle_request, store::ValueStore, table::RoutingTable,
+    };
+
+    let identity = Identity::generate();
+    let codec = Libp2pCodec::new(&identity);
+    let config = KademliaConfig::default();
+    let id = KeySpace::Libp2p.node_id(&identity.public_key());
+    let local = Node::new(SocketAddr::from(([127, 0, 0, 1], 4002)), id);
+    let (n_buckets, k_param) = (config.n_buckets, config.k_param);
+    let routes = RoutingTable::new(local, n_buckets, k_param, config.puzzle_difficulty);
+    let routes = Mutex::new(routes);
+    let store = Mutex::new(ValueStore::new(config.max_store_size));
+
+    let peer = SocketAddr::from(([127, 0, 0, 1], 4001));
+    let peer_id = Libp2pCodec::peer_id(&Identity::generate().public_key());
+
+    // ADD_PROVIDER as go-libp2p-kad-dht sends it, without write token
+    let add_provider = pb::Message {
+        key: b"provided".to_vec(),
+        provider_peers: vec![pb::Peer {
+            id: peer_id.clone(),
+            addrs: vec![encode_multiaddr(peer)],
+            connection: pb::ConnectionType::Connected.into(),
+        }],
+        ..message(MessageType::AddProvider)
+    };
+    let frame = add_provider.encode_length_delimited_to_vec();
+    let request = codec.decode_request(&frame, &peer_id, peer).unwrap();
+    let Message::Request(payload) = request.message else {
+        panic!("ADD_PROVIDER is a request");
+    };
+
+    let response = handle_request(payload, request.source, &routes, &store, &config);
+    assert!(matches!(response, Response::AddProvider));
+    assert_eq!(
+        store
+            .expect_lock()
+            .get_providers(&Key::from_hash(b"provided")),
+        vec![request.source]
+    );
+
+    // native messages can't skip the write token
+    assert!(bincode::serialize(&WriteToken::Transport).is_err());
+    assert!(bincode::deserialize::<WriteToken>(&1u32.to_le_bytes()).is_err());
+}
+
+#[cfg(test)]
+fn hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap())
+        .collect()
+}
@@ -0,0 +1,180 @@
+//! Wire formats [`RpcMessage`]s are encoded in by a [`Transport`](crate::transport::Transport)
+
+#[cfg(feature = "mainline")]
+mod bencode;
+#[cfg(feature = "mainline")]
+mod bep5;
+#[cfg(feature = "libp2p")]
+mod libp2p;
+
+#[cfg(feature = "mainline")]
+pub use bep5::Bep5Codec;
+#[cfg(feature = "libp2p")]
+pub use libp2p::Libp2pCodec;
+
+use crate::{
+    error::{Error, Result},
+    types::{
+        key::Key,
+        messages::{Message, Response, RpcMessage},
+        node::Node,
+    },
+    KEY_SIZE,
+};
+use std::net::SocketAddr;
+
+/// Starts every native datagram, followed by protocol version and network id
+pub const MAGIC: [u8; 4] = *b"KDHT";
+
+/// Version of the native wire format, it changes with every incompatible change of messages
+pub const PROTOCOL_VERSION: u16 = 1;
+
+pub(crate) const HEADER_SIZE: usize = 10;
+
+/// Starts native datagrams refusing a message of other protocol version or network,
+/// followed by version and network id of the refusing node and token of the message
+pub const REJECTION_MAGIC: [u8; 4] = *b"KDRJ";
+
+/// Payload of every protocol version starts with the token as bincode encodes it
+const TOKEN_SIZE: usize = 16;
+
+pub trait Codec: Send + Sync + 'static {
+    fn encode(&self, msg: &RpcMessage) -> Result<Vec<u8>>;
+
+    /// Decodes message received from address, formats that don't carry
+    /// the source address take it from there
+    fn decode(&self, bytes: &[u8], from: SocketAddr) -> Result<RpcMessage>;
+
+    /// Reply to the sender of a message decoding failed with err, telling it the
+    /// message can't be understood here. `None` if there is nothing to tell
+    fn reject(&self, _bytes: &[u8], _err: &Error) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// Native format, header followed by bincode of the whole signed message.
+/// Messages of other protocol versions or networks are refused before their
+/// payload is decoded and answered with a rejection any version understands,
+/// so nodes running an incompatible version neither misread each other nor
+/// look unresponsive
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BincodeCodec {
+    network_id: u32,
+}
+
+impl BincodeCodec {
+    pub fn new(network_id: u32) -> Self {
+        Self { network_id }
+    }
+}
+
+impl Codec for BincodeCodec {
+    fn encode(&self, msg: &RpcMessage) -> Result<Vec<u8>> {
+        let mut datagram = Vec::with_capacity(HEADER_SIZE);
+        datagram.extend(MAGIC);
+        datagram.extend(PROTOCOL_VERSION.to_be_bytes());
+        datagram.extend(self.network_id.to_be_bytes());
+        datagram.extend(msg.to_bytes()?);
+        Ok(datagram)
+    }
+
+    fn decode(&self, bytes: &[u8], from: SocketAddr) -> Result<RpcMessage> {
+        let Some((header, payload)) = bytes.split_first_chunk::<HEADER_SIZE>() else {
+            return Err(Error::Codec("shorter than header".to_owned()));
+        };
+
+        let [m0, m1, m2, m3, v0, v1, n0, n1, n2, n3] = *header;
+        if [m0, m1, m2, m3] == REJECTION_MAGIC {
+            return decode_rejection(header, payload, from);
+        }
+        if [m0, m1, m2, m3] != MAGIC {
+            return Err(Error::Codec("missing magic bytes".to_owned()));
+        }
+
+        let version = u16::from_be_bytes([v0, v1]);
+        if version != PROTOCOL_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        let network_id = u32::from_be_bytes([n0, n1, n2, n3]);
+        if network_id != self.network_id {
+            return Err(Error::WrongNetwork(network_id));
+        }
+
+        RpcMessage::from_bytes(payload)
+    }
+
+    fn reject(&self, bytes: &[u8], err: &Error) -> Option<Vec<u8>> {
+        if !matches!(err, Error::UnsupportedVersion(_) | Error::WrongNetwork(_)) {
+            return None;
+        }
+
+        let token = bytes.get(HEADER_SIZE..HEADER_SIZE + TOKEN_SIZE)?;
+        let mut datagram = Vec::with_capacity(HEADER_SIZE + TOKEN_SIZE);
+        datagram.extend(REJECTION_MAGIC);
+        datagram.extend(PROTOCOL_VERSION.to_be_bytes());
+        datagram.extend(self.network_id.to_be_bytes());
+        datagram.extend_from_slice(token);
+        Some(datagram)
+    }
+}
+
+/// Rejection names neither its sender nor is signed, source has zeroed id and
+/// the token of the request is what authenticates it
+fn decode_rejection(
+    header: &[u8; HEADER_SIZE],
+    payload: &[u8],
+    from: SocketAddr,
+) -> Result<RpcMessage> {
+    let token = <[u8; TOKEN_SIZE]>::try_from(payload)
+        .map_err(|_| Error::Codec("invalid rejection".to_owned()))?;
+    let [_, _, _, _, v0, v1, n0, n1, n2, n3] = *header;
+
+    let version = u16::from_be_bytes([v0, v1]);
+    let network_id = u32::from_be_bytes([n0, n1, n2, n3]);
+    Ok(RpcMessage::unsigned(
+        u128::from_le_bytes(token),
+        Node::new(from, Key([0; KEY_SIZE])),
+        Message::Response(Response::Incompatible(version, network_id)),
+    ))
+}
+
+#[test]
+fn bincode_codec_test() {
+    use crate::{types::messages::Request, Identity};
+
+    let identity = Identity::generate();
+    let addr = SocketAddr::from(([127, 0, 0, 1], 4000));
+    let source = Node::new(addr, identity.id());
+    let ping = RpcMessage::signed(1, source, Message::Request(Request::Ping), &identity).unwrap();
+
+    let codec = BincodeCodec::new(7);
+    let encoded = codec.encode(&ping).unwrap();
+    assert_eq!(&encoded[..HEADER_SIZE], b"KDHT\x00\x01\x00\x00\x00\x07");
+    assert!(codec.decode(&encoded, addr).unwrap().verify().is_ok());
+
+    let other_network = BincodeCodec::new(8).decode(&encoded, addr);
+    assert!(matches!(other_network, Err(Error::WrongNetwork(7))));
+
+    let mut newer = encoded.clone();
+    newer[5] = 2;
+    let newer = codec.decode(&newer, addr);
+    assert!(matches!(newer, Err(Error::UnsupportedVersion(2))));
+
+    // sender learns the message was refused
+    let rejection = BincodeCodec::new(8)
+        .reject(&encoded, &Error::WrongNetwork(7))
+        .unwrap();
+    assert_eq!(&rejection[..HEADER_SIZE], b"KDRJ\x00\x01\x00\x00\x00\x08");
+    let rejection = codec.decode(&rejection, addr).unwrap();
+    assert_eq!(rejection.token, 1);
+    assert!(!rejection.is_signed());
+    assert!(matches!(
+        rejection.message,
+        Message::Response(Response::Incompatible(1, 8))
+    ));
+    assert!(codec.reject(&encoded, &Error::InvalidSignature).is_none());
+
+    let headerless = codec.decode(&ping.to_bytes().unwrap(), addr);
+    assert!(matches!(headerless, Err(Error::Codec(_))));
+}
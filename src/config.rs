@@ -0,0 +1,332 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+use crate::{puzzle::PuzzleDifficulty, types::key::KeySpace, KEY_SIZE};
+
+/// Addresses the node binds to and advertises to its peers
+#[derive(Clone, Copy, Debug)]
+pub struct AddressConfig {
+    /// Local address the socket is bound to
+    pub bind: SocketAddr,
+    /// Address peers should use to reach this node, defaults to the bound address
+    pub external: Option<SocketAddr>,
+}
+
+impl AddressConfig {
+    pub fn new(bind: SocketAddr) -> Self {
+        Self {
+            bind,
+            external: None,
+        }
+    }
+
+    pub fn with_external(mut self, external: SocketAddr) -> Self {
+        self.external = Some(external);
+        self
+    }
+}
+
+impl Default for AddressConfig {
+    /// Binds all interfaces on a port chosen by the OS
+    fn default() -> Self {
+        Self::new(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)))
+    }
+}
+
+/// Protocol parameters of a [`Kademlia`](crate::Kademlia) node, created with [`KademliaConfig::builder`]
+#[derive(Clone, Copy, Debug)]
+pub struct KademliaConfig {
+    pub(crate) addresses: AddressConfig,
+    pub(crate) n_buckets: usize,
+    pub(crate) k_param: usize,
+    pub(crate) alpha: usize,
+    pub(crate) disjoint_paths: usize,
+    pub(crate) request_timeout: Duration,
+    pub(crate) refresh_interval: Duration,
+    pub(crate) snapshot_interval: Duration,
+    pub(crate) record_ttl: Duration,
+    pub(crate) republish_interval: Duration,
+    pub(crate) publish_interval: Duration,
+    pub(crate) max_store_size: usize,
+    pub(crate) max_datagram_size: usize,
+    pub(crate) mtu: usize,
+    pub(crate) tcp: bool,
+    pub(crate) puzzle_difficulty: PuzzleDifficulty,
+    pub(crate) allow_unsigned: bool,
+    pub(crate) network_id: u32,
+    pub(crate) key_space: KeySpace,
+}
+
+/// Buckets and records are checked at least this often
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(60);
+
+impl KademliaConfig {
+    pub fn builder() -> KademliaConfigBuilder {
+        KademliaConfigBuilder::default()
+    }
+
+    /// How often background task checks for stale buckets and records to republish
+    pub(crate) fn maintenance_interval(&self) -> Duration {
+        MAINTENANCE_INTERVAL
+            .min(self.refresh_interval)
+            .min(self.republish_interval)
+            .min(self.publish_interval)
+    }
+
+    pub fn addresses(&self) -> AddressConfig {
+        self.addresses
+    }
+
+    pub fn n_buckets(&self) -> usize {
+        self.n_buckets
+    }
+
+    pub fn k_param(&self) -> usize {
+        self.k_param
+    }
+
+    pub fn alpha(&self) -> usize {
+        self.alpha
+    }
+
+    pub fn disjoint_paths(&self) -> usize {
+        self.disjoint_paths
+    }
+
+    pub fn request_timeout(&self) -> Duration {
+        self.request_timeout
+    }
+
+    pub fn refresh_interval(&self) -> Duration {
+        self.refresh_interval
+    }
+
+    pub fn snapshot_interval(&self) -> Duration {
+        self.snapshot_interval
+    }
+
+    pub fn record_ttl(&self) -> Duration {
+        self.record_ttl
+    }
+
+    pub fn republish_interval(&self) -> Duration {
+        self.republish_interval
+    }
+
+    pub fn publish_interval(&self) -> Duration {
+        self.publish_interval
+    }
+
+    pub fn max_store_size(&self) -> usize {
+        self.max_store_size
+    }
+
+    pub fn max_datagram_size(&self) -> usize {
+        self.max_datagram_size
+    }
+
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    pub fn tcp(&self) -> bool {
+        self.tcp
+    }
+
+    pub fn puzzle_difficulty(&self) -> PuzzleDifficulty {
+        self.puzzle_difficulty
+    }
+
+    pub fn allow_unsigned(&self) -> bool {
+        self.allow_unsigned
+    }
+
+    pub fn network_id(&self) -> u32 {
+        self.network_id
+    }
+
+    pub fn key_space(&self) -> KeySpace {
+        self.key_space
+    }
+}
+
+impl Default for KademliaConfig {
+    fn default() -> Self {
+        Self {
+            addresses: AddressConfig::default(),
+            n_buckets: KEY_SIZE * 8,
+            k_param: 20,
+            alpha: 3,
+            disjoint_paths: 1,
+            request_timeout: Duration::from_secs(1),
+            refresh_interval: Duration::from_secs(60 * 60),
+            snapshot_interval: Duration::from_secs(10 * 60),
+            record_ttl: Duration::from_secs(25 * 60 * 60),
+            republish_interval: Duration::from_secs(60 * 60),
+            publish_interval: Duration::from_secs(24 * 60 * 60),
+            max_store_size: 64 * 1024 * 1024,
+            max_datagram_size: 4096,
+            mtu: 1232,
+            tcp: false,
+            puzzle_difficulty: PuzzleDifficulty::default(),
+            allow_unsigned: false,
+            network_id: 0,
+            key_space: KeySpace::Native,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct KademliaConfigBuilder {
+    config: KademliaConfig,
+}
+
+impl KademliaConfigBuilder {
+    pub fn bind_addr(mut self, addr: SocketAddr) -> Self {
+        self.config.addresses.bind = addr;
+        self
+    }
+
+    pub fn external_addr(mut self, addr: SocketAddr) -> Self {
+        self.config.addresses.external = Some(addr);
+        self
+    }
+
+    pub fn addresses(mut self, addresses: AddressConfig) -> Self {
+        self.config.addresses = addresses;
+        self
+    }
+
+    /// Number of buckets, at least 1 and at most the bits of a key in the key space,
+    /// which is the default. With fewer buckets the last one holds every node closer than
+    /// the ones before it
+    pub fn n_buckets(mut self, n_buckets: usize) -> Self {
+        self.config.n_buckets = n_buckets.clamp(1, KEY_SIZE * 8);
+        self
+    }
+
+    /// Bucket size and number of nodes returned by lookups, at least 1
+    pub fn k_param(mut self, k_param: usize) -> Self {
+        self.config.k_param = k_param.max(1);
+        self
+    }
+
+    /// Number of parallel requests in a lookup round, at least 1
+    pub fn alpha(mut self, alpha: usize) -> Self {
+        self.config.alpha = alpha.max(1);
+        self
+    }
+
+    /// Number of disjoint paths node lookups are split into, at least 1.
+    /// Every node is queried on at most one path, so a malicious node
+    /// can only mislead the paths it was reached by
+    pub fn disjoint_paths(mut self, paths: usize) -> Self {
+        self.config.disjoint_paths = paths.max(1);
+        self
+    }
+
+    /// How long to wait for response before the request fails
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.config.request_timeout = timeout;
+        self
+    }
+
+    /// Buckets without lookups for this long are refreshed
+    pub fn refresh_interval(mut self, interval: Duration) -> Self {
+        self.config.refresh_interval = interval;
+        self
+    }
+
+    /// How often state is saved after [`Kademlia::start_snapshots`](crate::Kademlia::start_snapshots)
+    pub fn snapshot_interval(mut self, interval: Duration) -> Self {
+        self.config.snapshot_interval = interval;
+        self
+    }
+
+    /// Longest time stored value or provider record is kept, publishers ask for this ttl.
+    /// Should be longer than publish interval so records live until they are refreshed
+    pub fn record_ttl(mut self, ttl: Duration) -> Self {
+        self.config.record_ttl = ttl;
+        self
+    }
+
+    /// Held values are stored again to the k closest nodes after this interval
+    pub fn republish_interval(mut self, interval: Duration) -> Self {
+        self.config.republish_interval = interval;
+        self
+    }
+
+    /// Values put by this node are stored again with fresh ttl and provided keys
+    /// are announced again after this interval
+    pub fn publish_interval(mut self, interval: Duration) -> Self {
+        self.config.publish_interval = interval;
+        self
+    }
+
+    /// Most bytes of values with their keys held for other nodes, STORE requests
+    /// that don't fit are refused. Default 64 MiB
+    pub fn max_store_size(mut self, size: usize) -> Self {
+        self.config.max_store_size = size;
+        self
+    }
+
+    /// Size of receive buffer, longer datagrams are truncated. Should not be below the MTU of peers
+    pub fn max_datagram_size(mut self, size: usize) -> Self {
+        self.config.max_datagram_size = size;
+        self
+    }
+
+    /// Longest datagram sent, at least 576 bytes. FIND_NODE and FIND_VALUE responses
+    /// leave out their farthest nodes to fit in one datagram, other longer messages
+    /// are split into fragments. Default 1232 fits in IPv6 minimum MTU
+    pub fn mtu(mut self, mtu: usize) -> Self {
+        self.config.mtu = mtu.max(576);
+        self
+    }
+
+    /// Send messages longer than the MTU over TCP connections to the peer's port
+    /// instead of in UDP fragments, see [`HybridTransport`](crate::transport::HybridTransport).
+    /// Every node of the network has to enable it
+    pub fn tcp(mut self, enabled: bool) -> Self {
+        self.config.tcp = enabled;
+        self
+    }
+
+    /// Leading zero bits of S/Kademlia static and dynamic puzzles, nodes that
+    /// don't solve them are not admitted to the routing table. Own identity has
+    /// to be generated with [`Identity::generate_with_puzzle`](crate::Identity::generate_with_puzzle).
+    /// Bits are clamped to [`PuzzleDifficulty::MAX_BITS`]
+    pub fn puzzle_difficulty(mut self, static_bits: u32, dynamic_bits: u32) -> Self {
+        self.config.puzzle_difficulty = PuzzleDifficulty::new(static_bits, dynamic_bits);
+        self
+    }
+
+    /// Accept messages without signature, needed for wire formats that can't carry one
+    /// such as [`Bep5Codec`](crate::codec::Bep5Codec). Node ids of unsigned peers are not verified
+    pub fn allow_unsigned(mut self, allow: bool) -> Self {
+        self.config.allow_unsigned = allow;
+        self
+    }
+
+    /// Sent in header of every native message, messages of other networks are dropped.
+    /// Keeps unrelated DHTs that share ports from merging
+    pub fn network_id(mut self, network_id: u32) -> Self {
+        self.config.network_id = network_id;
+        self
+    }
+
+    /// 256-bit native keys by default, every node of the network has to use the same
+    /// key space. Node id is derived from identity in it
+    pub fn key_space(mut self, key_space: KeySpace) -> Self {
+        self.config.key_space = key_space;
+        self
+    }
+
+    pub fn build(mut self) -> KademliaConfig {
+        let key_bits = self.config.key_space.key_size() * 8;
+        self.config.n_buckets = self.config.n_buckets.min(key_bits);
+        self.config
+    }
+}
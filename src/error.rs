@@ -0,0 +1,114 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    io,
+};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Socket could not be bound, read or written
+    Io(io::Error),
+    /// Message could not be serialized or deserialized
+    Encoding(bincode::Error),
+    /// Peer didn't respond before request timeout
+    Timeout,
+    /// Peer responded with message that doesn't match the request
+    UnexpectedResponse,
+    /// No node responded during lookup
+    LookupFailed,
+    /// Network interface stopped before request was completed
+    Disconnected,
+    /// Key or keypair has invalid length or encoding
+    InvalidKey,
+    /// Message signature doesn't verify or public key doesn't match node id
+    InvalidSignature,
+    /// Node id doesn't solve the crypto puzzle required by configuration
+    PuzzleNotSolved,
+    /// Puzzle of this many bits is too hard to solve, see [`PuzzleDifficulty::MAX_BITS`](crate::PuzzleDifficulty::MAX_BITS)
+    PuzzleTooHard(u32),
+    /// Message doesn't follow the wire format of a [`Codec`](crate::codec::Codec)
+    Codec(String),
+    /// Peer refused the request, with reason
+    Rejected(String),
+    /// Peer runs other protocol version or network, with its version and network id
+    Incompatible(u16, u32),
+    /// Message uses protocol version this node doesn't understand
+    UnsupportedVersion(u16),
+    /// Message belongs to a network with other id
+    WrongNetwork(u32),
+    /// Encoded message of this many bytes needs more fragments than can be sent
+    MessageTooLong(usize),
+}
+
+impl Error {
+    /// Errors caused by the remote peer, these remove the peer from routing table
+    pub fn is_peer_failure(&self) -> bool {
+        matches!(
+            self,
+            Error::Timeout | Error::UnexpectedResponse | Error::Incompatible(..)
+        )
+    }
+
+    /// Errors of received messages that can't be used, these are skipped
+    pub fn is_invalid_message(&self) -> bool {
+        matches!(
+            self,
+            Error::Encoding(_)
+                | Error::Codec(_)
+                | Error::UnsupportedVersion(_)
+                | Error::WrongNetwork(_)
+        )
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "IO error: {}", err),
+            Error::Encoding(err) => write!(f, "Encoding error: {}", err),
+            Error::Timeout => write!(f, "Request timed out"),
+            Error::UnexpectedResponse => write!(f, "Unexpected response"),
+            Error::LookupFailed => write!(f, "No node responded during lookup"),
+            Error::Disconnected => write!(f, "Network interface disconnected"),
+            Error::InvalidKey => write!(f, "Invalid key"),
+            Error::InvalidSignature => write!(f, "Invalid message signature"),
+            Error::PuzzleNotSolved => write!(f, "Node id doesn't solve crypto puzzle"),
+            Error::PuzzleTooHard(bits) => write!(f, "Puzzle of {} bits is too hard", bits),
+            Error::Codec(reason) => write!(f, "Invalid wire message: {}", reason),
+            Error::Rejected(reason) => write!(f, "Request rejected by peer: {}", reason),
+            Error::Incompatible(version, network_id) => write!(
+                f,
+                "Peer runs protocol version {} of network {}",
+                version, network_id
+            ),
+            Error::UnsupportedVersion(version) => {
+                write!(f, "Unsupported protocol version {}", version)
+            }
+            Error::WrongNetwork(network_id) => write!(f, "Message from network {}", network_id),
+            Error::MessageTooLong(len) => write!(f, "Message of {} bytes is too long", len),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Encoding(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<bincode::Error> for Error {
+    fn from(err: bincode::Error) -> Self {
+        Error::Encoding(err)
+    }
+}
@@ -1,4 +1,10 @@
-use std::sync::{Mutex, MutexGuard};
+use rand::{rngs::OsRng, Rng};
+use std::{
+    fs, io,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
 
 pub trait ExpectLock<T: ?Sized> {
     fn expect_lock(&self) -> MutexGuard<'_, T>;
@@ -9,3 +15,37 @@ impl<T: ?Sized> ExpectLock<T> for Mutex<T> {
         self.lock().expect("Error locking")
     }
 }
+
+/// Replaces file with contents readable only by owner on unix,
+/// written to temporary file first so readers never see partial contents.
+/// Temporary file is named after the whole file name with a random suffix,
+/// so concurrent writes of this or other files never share it
+pub fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(format!(".{:016x}.tmp", OsRng.gen::<u64>()));
+    let temporary = PathBuf::from(temporary);
+
+    let written = create_private(&temporary)
+        .and_then(|mut file| file.write_all(contents))
+        .and_then(|()| fs::rename(&temporary, path));
+
+    if written.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    written
+}
+
+/// New file that only owner can access on unix, permissions are set
+/// when it is created so contents are never readable by others
+fn create_private(path: &Path) -> io::Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options.open(path)
+}
@@ -0,0 +1,99 @@
+use crate::{
+    error::{Error, Result},
+    helpers,
+    puzzle::PuzzleDifficulty,
+    types::key::Key,
+};
+
+use ed25519_dalek::{SecretKey, Signature, Signer, SigningKey, VerifyingKey};
+use rand::rngs::OsRng;
+use std::{fs, io, path::Path};
+
+/// Multihash code of identity, digest is the hashed bytes themselves
+const IDENTITY: u8 = 0x00;
+
+/// Protobuf encoded public key of libp2p starts with key type Ed25519 and data length
+const ED25519_PUBLIC_KEY: [u8; 4] = [0x08, 0x01, 0x12, 0x20];
+
+/// Ed25519 keypair of a node, node id is hash of the public key
+#[derive(Clone)]
+pub struct Identity {
+    signing_key: SigningKey,
+}
+
+impl Identity {
+    pub fn generate() -> Self {
+        Self {
+            signing_key: SigningKey::generate(&mut OsRng),
+        }
+    }
+
+    /// Generates keypairs until native node id solves static puzzle with given difficulty,
+    /// every extra bit doubles expected time. Bits are clamped to
+    /// [`PuzzleDifficulty::MAX_BITS`]
+    pub fn generate_with_puzzle(static_bits: u32) -> Self {
+        let difficulty = PuzzleDifficulty::new(static_bits, 0);
+
+        loop {
+            let identity = Self::generate();
+            if difficulty.is_static_solved(&identity.id()) {
+                return identity;
+            }
+        }
+    }
+
+    pub fn from_secret_key(secret_key: SecretKey) -> Self {
+        Self {
+            signing_key: SigningKey::from_bytes(&secret_key),
+        }
+    }
+
+    pub fn secret_key(&self) -> SecretKey {
+        self.signing_key.to_bytes()
+    }
+
+    pub fn public_key(&self) -> VerifyingKey {
+        self.signing_key.verifying_key()
+    }
+
+    /// Node id in the native key space, see [`KeySpace::node_id`](crate::KeySpace::node_id)
+    pub fn id(&self) -> Key {
+        Key::from_public_key(&self.public_key())
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Signature {
+        self.signing_key.sign(message)
+    }
+
+    /// Writes secret key to file, readable only by owner on unix
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        Ok(helpers::write_private(path.as_ref(), &self.secret_key())?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let secret_key = fs::read(path)?.try_into().map_err(|_| Error::InvalidKey)?;
+
+        Ok(Self::from_secret_key(secret_key))
+    }
+
+    /// Loads identity from file, new one is generated and saved if file doesn't exist
+    pub fn load_or_generate(path: impl AsRef<Path>) -> Result<Self> {
+        match Self::load(&path) {
+            Err(Error::Io(err)) if err.kind() == io::ErrorKind::NotFound => {
+                let identity = Self::generate();
+                identity.save(path)?;
+                Ok(identity)
+            }
+            result => result,
+        }
+    }
+}
+
+/// Peer id of libp2p of ed25519 public key, identity multihash of the protobuf
+/// encoded key
+pub(crate) fn peer_id(public_key: &VerifyingKey) -> Vec<u8> {
+    let mut peer_id = vec![IDENTITY, (ED25519_PUBLIC_KEY.len() + 32) as u8];
+    peer_id.extend(ED25519_PUBLIC_KEY);
+    peer_id.extend(public_key.as_bytes());
+    peer_id
+}
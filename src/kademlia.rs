@@ -1,69 +1,74 @@
 use crate::{
+    config::KademliaConfig,
+    error::{Error, Result},
     helpers::ExpectLock,
+    identity::Identity,
+    protocol::{self, Protocol, ValueLookup},
+    shortlist::Shortlist,
     socket::NetworkInterface,
-    table,
+    state::State,
+    transport::{self, Transport},
     types::{
         distance::NodeDistance,
         key::Key,
-        messages::{Message, Request, Response, RpcMessage, RpcRequest},
+        lookup::{LookupResult, LookupStats},
+        messages::{FindValueResult, Message, Request, Response, RpcRequest, WriteToken},
         node::Node,
     },
 };
 
 use std::{
-    collections::{BinaryHeap, HashSet},
+    collections::HashSet,
+    net::SocketAddr,
+    path::{Path, PathBuf},
     sync::{mpsc, Arc, Mutex},
     thread::{self, JoinHandle},
+    time::Duration,
 };
 
-#[derive(Clone, Copy)]
-#[allow(unused)]
-struct KademliaConfig {
-    key_length: usize,
-    n_buckets: usize,
-    k_param: usize,
-    alpha: usize,
-}
-
-impl Default for KademliaConfig {
-    fn default() -> Self {
-        Self {
-            key_length: 32,
-            n_buckets: 32 * 8,
-            k_param: 20,
-            alpha: 3,
-        }
-    }
-}
-
 #[derive(Clone)]
 /// Clone should be removed with Arc and Mutex
 /// and replaced with some sort of queue
 pub struct Kademlia {
-    routes: Arc<Mutex<table::RoutingTable>>,
+    protocol: Arc<Protocol>,
     rpc: Arc<NetworkInterface>,
-    node: Node,
-    config: KademliaConfig,
 }
 
 impl Kademlia {
-    pub fn new(port: u16, peer_id: Key) -> Self {
-        let node = Node::new(port, peer_id);
-        let config = KademliaConfig::default();
+    pub fn new(addr: SocketAddr, identity: Identity) -> Result<Self> {
+        Self::with_config(KademliaConfig::builder().bind_addr(addr).build(), identity)
+    }
 
-        let mut routes = table::RoutingTable::new(node, config.n_buckets, config.k_param);
-        routes.update(node);
+    /// Messages go over UDP, or over TCP as well if enabled in config
+    pub fn with_config(config: KademliaConfig, identity: Identity) -> Result<Self> {
+        let transport = transport::bind(&config)?;
+        Self::start(config, identity, transport)
+    }
 
-        let (rpc_sender, rpc_receiver) = mpsc::channel();
+    /// Node that exchanges messages over given transport instead of UDP,
+    /// bind address from config is not used
+    pub fn with_transport(
+        config: KademliaConfig,
+        identity: Identity,
+        transport: impl Transport,
+    ) -> Result<Self> {
+        Self::start(config, identity, Arc::new(transport))
+    }
 
-        let rpc = NetworkInterface::new(node);
+    fn start(
+        config: KademliaConfig,
+        identity: Identity,
+        transport: Arc<dyn Transport>,
+    ) -> Result<Self> {
+        let rpc = NetworkInterface::new(&config, identity.clone(), transport)?;
+        let protocol = Protocol::new(config, identity, rpc.node())?;
+
+        let (rpc_sender, rpc_receiver) = mpsc::channel();
         rpc.clone().spawn(rpc_sender);
 
         let kademlia = Self {
-            routes: Arc::new(Mutex::new(routes)),
+            protocol: Arc::new(protocol),
             rpc: Arc::new(rpc),
-            node,
-            config,
         };
 
         let protocol = kademlia.clone();
@@ -75,134 +80,430 @@ impl Kademlia {
             }
         });
 
+        let protocol = kademlia.clone();
+        thread::spawn(move || loop {
+            thread::sleep(protocol.protocol.config.maintenance_interval());
+            protocol.refresh_buckets();
+            protocol.maintain_records();
+        });
+
         // dbg!(&kademlia.routes.expect_lock());
-        kademlia
+        Ok(kademlia)
     }
 
     fn respond(&self, request: RpcRequest) {
-        {
-            let mut routes = self.routes.expect_lock();
-            let source_node = Node::new(request.source, Key::new(request.source.to_string()));
-            routes.update(source_node); // Add node that request to know nodes
+        let (token, from) = (request.token, request.from);
+        let (response, least_recent) = self.protocol.answer(request);
+
+        let sent = self.rpc.send_msg(token, Message::Response(response), from);
+
+        if let Err(err) = sent {
+            error!("Error responding to {}: {}", from, err);
+        }
+
+        // Liveness check is done after responding since it waits for network
+        self.evict_if_unresponsive(least_recent);
+    }
+
+    /// Pings least recently seen node of full bucket, see [`Protocol::update_route`].
+    /// Routing table is never locked while waiting for network
+    fn evict_if_unresponsive(&self, least_recent: Option<Node>) {
+        if let Some(node) = least_recent {
+            // failed ping removes node and promotes a replacement
+            let _ = self.ping(node);
+            self.protocol.pinged(&node);
         }
+    }
 
-        let response = match request.payload {
-            Request::Ping => Response::Pong,
-            Request::FindNode(ref id) => {
-                let routes = self.routes.expect_lock();
-                let result = routes.get_closest_nodes(id, self.config.k_param);
-                Response::FindNode(result)
+    /// Looks up random key in range of every bucket that had no lookup for refresh interval
+    fn refresh_buckets(&self) {
+        for (index, key) in self.protocol.refresh_keys() {
+            debug!("Refreshing bucket {}", index);
+            if let Err(err) = self.lookup_nodes(&key) {
+                warn!("Error refreshing bucket {}: {}", index, err);
             }
-        };
+        }
+    }
 
-        let msg = RpcMessage {
-            token: request.token,
-            source: self.node.port,
-            message: Message::Response(response),
-        };
+    /// Writes identity and routing table peers to file
+    pub fn save_state(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.protocol.state().save(path.as_ref())
+    }
+
+    /// Starts node with identity from state file, restored peers are pinged
+    /// `alpha` at a time and only those that respond are added to the routing table
+    pub fn load_state(path: impl AsRef<Path>, config: KademliaConfig) -> Result<Self> {
+        let State { secret_key, peers } = State::load(path.as_ref())?;
+
+        let kademlia = Self::with_config(config, Identity::from_secret_key(secret_key))?;
+
+        let mut restored = Vec::with_capacity(peers.len());
+        for batch in peers.chunks(kademlia.protocol.config.alpha) {
+            thread::scope(|scope| {
+                let pings = batch
+                    .iter()
+                    .map(|&(node, _)| {
+                        let kademlia = &kademlia;
+                        scope.spawn(move || kademlia.ping(node))
+                    })
+                    .collect::<Vec<_>>();
+
+                let responded = batch
+                    .iter()
+                    .zip(pings)
+                    .filter_map(|(peer, ping)| matches!(ping.join(), Ok(Ok(()))).then_some(*peer));
+                restored.extend(responded);
+            });
+        }
+
+        kademlia.protocol.restore(restored);
 
-        self.rpc.send_msg(msg, request.source);
+        Ok(kademlia)
     }
 
-    pub fn bootstrap(&mut self, node: Node) {
-        self.routes.expect_lock().update(node);
-        self.lookup_nodes(&self.node.id);
+    /// Saves state to file every snapshot interval
+    pub fn start_snapshots(&self, path: impl Into<PathBuf>) {
+        let path = path.into();
+        let protocol = self.clone();
+
+        thread::spawn(move || loop {
+            thread::sleep(protocol.protocol.config.snapshot_interval);
+            if let Err(err) = protocol.save_state(&path) {
+                error!("Error saving state to {}: {}", path.display(), err);
+            }
+        });
+    }
+
+    pub fn bootstrap(&mut self, node: Node) -> Result<()> {
+        // node is added once it responds, its puzzle nonce might not be known yet
+        self.ping(node)?;
+        self.lookup_nodes(&self.protocol.node.id)?;
+        Ok(())
     }
 
     pub fn node(&self) -> &Node {
-        &self.node
+        &self.protocol.node
+    }
+
+    pub fn identity(&self) -> &Identity {
+        &self.protocol.identity
+    }
+
+    /// Number of responses dropped because they didn't come from the node request was sent to
+    pub fn rejected_responses(&self) -> u64 {
+        self.rpc.rejected_responses()
     }
 
     pub fn get_all_know_nodes(&self) -> Vec<Node> {
-        self.routes
-            .expect_lock()
-            .get_kbuckets()
-            .iter()
-            .flat_map(|bucket| bucket.nodes.clone())
-            .collect()
+        self.protocol.known_nodes()
     }
 
-    pub fn ping(&self, dst: Node) -> bool {
-        let response = self
+    /// Sends request and waits for response or request timeout, `answer` takes
+    /// the answer out of response of expected kind, see [`protocol::expect`].
+    /// Responding peer is kept in routing table, unresponsive one is removed
+    fn query<T>(
+        &self,
+        request: Request,
+        dst: Node,
+        answer: impl FnOnce(Response) -> Option<T>,
+    ) -> Result<T> {
+        let received = self
             .rpc
-            .request(Request::Ping, dst)
-            .recv()
-            .expect("Error making request");
-
-        let mut routes = self.routes.expect_lock();
-
-        if let Some(Response::Pong) = response {
-            routes.update(dst);
-            true
-        } else {
-            error!("No pong from peer: {}:{}", dst.id, dst.port);
-            routes.remove(&dst.id);
-            false
+            .request(request, dst)
+            .and_then(|receiver| receiver.recv().map_err(|_| Error::Disconnected))
+            .and_then(|received| received.ok_or(Error::Timeout));
+        let result = received.and_then(|received| protocol::expect(received, answer));
+
+        let (result, least_recent) = self.protocol.record_liveness(dst, result);
+        self.evict_if_unresponsive(least_recent);
+        result
+    }
+
+    pub fn ping(&self, dst: Node) -> Result<()> {
+        self.query(Request::Ping, dst, |response| {
+            matches!(response, Response::Pong).then_some(())
+        })
+    }
+
+    pub fn find_node(&self, dst: Node, id: Key) -> Result<Vec<Node>> {
+        self.query(Request::FindNode(id), dst, |response| match response {
+            Response::FindNode(nodes) => Some(nodes),
+            _ => None,
+        })
+    }
+
+    /// Stores value on dst, it expires after ttl or after record ttl of dst if that is shorter
+    pub fn store(&self, dst: Node, key: Key, value: Vec<u8>, ttl: Duration) -> Result<()> {
+        self.store_request(dst, Request::Store(key, value, ttl, false))
+    }
+
+    /// Stores copy of value on dst that is not republished
+    fn cache(&self, dst: Node, key: Key, value: Vec<u8>, ttl: Duration) -> Result<()> {
+        self.store_request(dst, Request::Store(key, value, ttl, true))
+    }
+
+    fn store_request(&self, dst: Node, request: Request) -> Result<()> {
+        self.query(request, dst, |response| {
+            matches!(response, Response::Store).then_some(())
+        })
+    }
+
+    pub fn find_value(&self, dst: Node, key: Key) -> Result<FindValueResult> {
+        self.query(Request::FindValue(key), dst, |response| match response {
+            Response::FindValue(found, result) if found == key => Some(result),
+            _ => None,
+        })
+    }
+
+    /// Announces this node as provider of key to dst, token is the write token
+    /// returned by [`Kademlia::find_providers`] from dst
+    pub fn add_provider(&self, dst: Node, key: Key, token: Vec<u8>) -> Result<()> {
+        let request = Request::AddProvider(key, WriteToken::Issued(token));
+        self.query(request, dst, |response| {
+            matches!(response, Response::AddProvider).then_some(())
+        })
+    }
+
+    /// Providers of key known to dst with write token for announcing to dst
+    pub fn find_providers(&self, dst: Node, key: Key) -> Result<(Vec<Node>, Vec<u8>)> {
+        self.query(Request::GetProviders(key), dst, |response| match response {
+            Response::GetProviders(providers, _, token) => Some((providers, token)),
+            _ => None,
+        })
+    }
+
+    /// Stores value on the k closest nodes to key, returns number of nodes that stored it.
+    /// Value is stored again every publish interval while this node runs
+    pub fn put(&self, key: Key, value: Vec<u8>) -> Result<usize> {
+        let key = self.protocol.key(&key);
+        self.protocol.publish(key, value.clone());
+        self.replicate(key, value, self.protocol.config.record_ttl)
+    }
+
+    /// Stops storing value put by this node again, copies held by other nodes
+    /// expire after record ttl
+    pub fn unpublish(&self, key: &Key) {
+        self.protocol.unpublish(&self.protocol.key(key));
+    }
+
+    fn replicate(&self, key: Key, value: Vec<u8>, ttl: Duration) -> Result<usize> {
+        let threads = self
+            .lookup_nodes(&key)?
+            .into_iter()
+            .map(|NodeDistance { node, .. }| {
+                let value = value.clone();
+                let protocol = self.clone();
+
+                thread::spawn(move || protocol.store(node, key, value, ttl))
+            })
+            .collect::<Vec<_>>();
+
+        let stored = threads
+            .into_iter()
+            .map(JoinHandle::join)
+            .filter(|r| matches!(r, Ok(Ok(()))))
+            .count();
+
+        Ok(stored)
+    }
+
+    /// Announces this node as provider of key to the k closest nodes to key,
+    /// returns number of nodes that accepted it. Key is announced again every
+    /// publish interval while this node runs
+    pub fn start_providing(&self, key: Key) -> Result<usize> {
+        let key = self.protocol.key(&key);
+        self.protocol.provide(key);
+        self.announce(key)
+    }
+
+    /// Stops announcing key again, provider records held by other nodes expire
+    /// after record ttl
+    pub fn stop_providing(&self, key: &Key) {
+        self.protocol.stop_providing(&self.protocol.key(key));
+    }
+
+    /// Every node is asked for write token before announcing
+    fn announce(&self, key: Key) -> Result<usize> {
+        let threads = self
+            .lookup_nodes(&key)?
+            .into_iter()
+            .map(|NodeDistance { node, .. }| {
+                let protocol = self.clone();
+                thread::spawn(move || {
+                    let (_, token) = protocol.find_providers(node, key)?;
+                    protocol.add_provider(node, key, token)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let announced = threads
+            .into_iter()
+            .map(JoinHandle::join)
+            .filter(|r| matches!(r, Ok(Ok(()))))
+            .count();
+
+        Ok(announced)
+    }
+
+    /// Providers of key known locally and to the k closest nodes to key
+    pub fn get_providers(&self, key: &Key) -> Result<Vec<Node>> {
+        let key = &self.protocol.key(key);
+        let mut providers = self.protocol.get_providers(key);
+
+        let threads = self
+            .lookup_nodes(key)?
+            .into_iter()
+            .map(|NodeDistance { node, .. }| {
+                let key = *key;
+                let protocol = self.clone();
+                thread::spawn(move || protocol.find_providers(node, key))
+            })
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            if let Ok(Ok((found, _))) = thread.join() {
+                providers.extend(found);
+            }
         }
+
+        Ok(protocol::unique_providers(providers))
     }
 
-    pub fn find_node(&self, dst: Node, id: Key) -> Option<Vec<NodeDistance>> {
-        let response = self
-            .rpc
-            .request(Request::FindNode(id), dst)
-            .recv()
-            .expect("Error making request");
-
-        let mut routes = self.routes.expect_lock();
-
-        if let Some(Response::FindNode(entries)) = response {
-            routes.update(dst);
-            Some(entries)
-        } else {
-            routes.remove(&dst.id);
-            None
+    /// Drops expired values and providers, republishes held values with their remaining ttl,
+    /// stores values published by this node again with fresh ttl and announces provided keys
+    fn maintain_records(&self) {
+        let (replicate, provide) = self.protocol.due_records();
+
+        for (key, value, ttl) in replicate {
+            if let Err(err) = self.replicate(key, value, ttl) {
+                warn!("Error republishing {}: {}", key, err);
+            }
+        }
+
+        for key in provide {
+            if let Err(err) = self.announce(key) {
+                warn!("Error announcing provider of {}: {}", key, err);
+            }
         }
     }
 
-    pub fn lookup_nodes(&self, id: &Key) -> Vec<NodeDistance> {
-        let mut nodes = vec![];
+    /// Iterative value lookup, stops after the round in which any node returned the value.
+    /// Value is then cached at the closest queried node that didn't have it
+    pub fn get(&self, key: &Key) -> Result<Option<Vec<u8>>> {
+        let key = &self.protocol.key(key);
+        if let Some(value) = self.protocol.get_value(key) {
+            return Ok(Some(value));
+        }
 
-        let mut to_query = {
-            let routes = self.routes.expect_lock();
-            BinaryHeap::from(routes.get_closest_nodes(id, self.config.k_param))
-        };
-        let mut queried = to_query.iter().map(Clone::clone).collect::<HashSet<_>>();
+        let mut lookup = ValueLookup::new(&self.protocol, key);
 
-        while !to_query.is_empty() {
-            let queries = (0..self.config.alpha)
-                .filter_map(|_| to_query.pop())
-                .collect::<Vec<_>>();
+        loop {
+            let batch = lookup.next_round();
+            if batch.is_empty() {
+                break;
+            }
 
-            let threads = queries
-                .iter()
-                .map(|NodeDistance { ref node, .. }| {
-                    let node = *node;
-                    let node_id = *id;
+            let threads = batch
+                .into_iter()
+                .map(|node| {
+                    let key = *key;
                     let protocol = self.clone();
 
-                    thread::spawn(move || protocol.find_node(node, node_id))
+                    (node, thread::spawn(move || protocol.find_value(node, key)))
                 })
                 .collect::<Vec<_>>();
 
-            threads
-                .into_iter()
-                .map(JoinHandle::join)
-                .filter_map(|r| r.ok().flatten())
-                .zip(queries)
-                .for_each(|(entries, query)| {
-                    nodes.push(query);
-
-                    for entry in entries {
-                        if queried.insert(entry.clone()) {
-                            to_query.push(entry);
-                        }
-                    }
-                });
+            for (node, thread) in threads {
+                let result = thread.join().unwrap_or(Err(Error::Disconnected));
+                lookup.record(&self.protocol, &node, result);
+            }
         }
 
-        nodes.sort_by(|a, b| a.distance.cmp(&b.distance));
-        nodes.truncate(self.config.k_param);
+        if let Some((node, value, ttl)) = lookup.cache_at() {
+            self.cache_value(node, *key, value, ttl);
+        }
 
-        nodes
+        lookup.finish()
+    }
+
+    /// Stores copy of found value at node in the background
+    fn cache_value(&self, node: Node, key: Key, value: Vec<u8>, ttl: Duration) {
+        let protocol = self.clone();
+
+        thread::spawn(move || {
+            if let Err(err) = protocol.cache(node, key, value, ttl) {
+                warn!("Error caching {} at {}: {}", key, node.addr, err);
+            }
+        });
+    }
+
+    /// k closest nodes to id, see [`Kademlia::lookup`]
+    pub fn lookup_nodes(&self, id: &Key) -> Result<Vec<NodeDistance>> {
+        Ok(self.lookup(id)?.nodes.into_iter().map(Into::into).collect())
+    }
+
+    /// Iterative node lookup split into configured number of disjoint paths.
+    /// Closest known nodes are dealt to the paths, each path keeps its own
+    /// shortlist and a node is queried by at most one path
+    pub fn lookup(&self, id: &Key) -> Result<LookupResult> {
+        let id = &self.protocol.key(id);
+        let queried = Mutex::new(HashSet::new());
+
+        let found = thread::scope(|scope| {
+            let handles = self
+                .protocol
+                .lookup_paths(id)
+                .into_iter()
+                .enumerate()
+                .map(|(path, shortlist)| {
+                    let queried = &queried;
+                    scope.spawn(move || (path, self.lookup_path(id, shortlist, queried)))
+                })
+                .collect::<Vec<_>>();
+
+            handles
+                .into_iter()
+                .filter_map(|h| h.join().ok())
+                .collect::<Vec<_>>()
+        });
+
+        self.protocol.merge_paths(found)
+    }
+
+    /// Runs lookup rounds until the k closest nodes of the path were queried
+    /// without finding closer node. Nodes queried by other paths are skipped
+    fn lookup_path(
+        &self,
+        id: &Key,
+        mut shortlist: Shortlist,
+        queried: &Mutex<HashSet<Key>>,
+    ) -> (Vec<NodeDistance>, LookupStats) {
+        loop {
+            let batch = shortlist.next_round(self.protocol.config.alpha, |node| {
+                queried.expect_lock().insert(node.id)
+            });
+
+            if batch.is_empty() {
+                return shortlist.finish();
+            }
+
+            let threads = batch
+                .into_iter()
+                .map(|node| {
+                    let node_id = *id;
+                    let protocol = self.clone();
+
+                    (
+                        node,
+                        thread::spawn(move || protocol.find_node(node, node_id)),
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            for (node, thread) in threads {
+                let result = thread.join().unwrap_or(Err(Error::Disconnected));
+                self.protocol.record_nodes(&mut shortlist, &node, result);
+            }
+        }
     }
 }
@@ -1,8 +1,4 @@
 #![feature(
-    const_for,
-    const_trait_impl,
-    effects,
-    const_mut_refs,
     iterator_try_collect
 )]
 
@@ -12,16 +8,38 @@ extern crate serde;
 #[macro_use]
 extern crate log;
 
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod codec;
+mod config;
+mod error;
+mod identity;
 mod kademlia;
+mod protocol;
+mod puzzle;
+mod shortlist;
 mod socket;
+mod state;
+mod store;
 mod table;
+mod token;
+pub mod transport;
 mod types;
 
 pub(crate) mod helpers;
 mod pure;
 
+pub use config::{AddressConfig, KademliaConfig, KademliaConfigBuilder};
+pub use error::{Error, Result};
+pub use identity::Identity;
 pub use kademlia::Kademlia;
-pub use types::key::Key;
+pub use puzzle::PuzzleDifficulty;
+pub use types::distance::{Distance, NodeDistance};
+pub use types::key::{Key, KeySpace};
+pub use types::lookup::{LookupEntry, LookupResult, LookupStats};
+pub use types::messages::{FindValueResult, Message, Request, Response, RpcMessage, WriteToken};
 pub use types::node::Node;
 
+/// Length of keys and node ids in bytes, key spaces with shorter keys leave the
+/// trailing bytes zero, see [`KeySpace`]
 const KEY_SIZE: usize = 32;
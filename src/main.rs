@@ -3,10 +3,12 @@
 use std::{
     env,
     io::{stdin, stdout, Write},
+    net::SocketAddr,
+    path::PathBuf,
 };
 use strum::{Display, EnumString};
 
-use kademlia::{Kademlia, Key, Node};
+use kademlia::{AddressConfig, Identity, Kademlia, KademliaConfig, Key, Node};
 
 #[derive(EnumString, Display)]
 enum Command {
@@ -16,24 +18,47 @@ enum Command {
     Print,
     #[strum(serialize = "bootstrap")]
     BootStrap,
+    #[strum(serialize = "put")]
+    Put,
+    #[strum(serialize = "get")]
+    Get,
 }
 
 fn main() {
-    let port = env::args()
-        .last()
-        .expect("Port must be provided")
-        .parse()
-        .expect("Error parsing");
+    let args = parse_args();
+    let config = KademliaConfig::builder().addresses(args.addresses).build();
 
-    let mut kademlia = Kademlia::new(port, Key::new(port.to_string()));
+    let mut kademlia = if args.state.exists() {
+        let kademlia = Kademlia::load_state(&args.state, config).expect("Error restoring state");
+        if args.identity.exists() {
+            let identity = Identity::load(&args.identity).expect("Error loading identity");
+            assert!(
+                identity.id() == kademlia.node().id,
+                "Identity in {} differs from the one saved in {}",
+                args.identity.display(),
+                args.state.display()
+            );
+        }
+        kademlia
+    } else {
+        let identity = Identity::load_or_generate(&args.identity).expect("Error loading identity");
+        Kademlia::with_config(config, identity).expect("Error starting node")
+    };
+    kademlia.start_snapshots(&args.state);
+    println!(
+        "Listening on {} as {}",
+        kademlia.node().addr,
+        kademlia.node().id
+    );
 
     loop {
         let command = get_command();
 
         match command {
             Command::AddPeer => {
-                let port = read("Enter port: ").parse().expect("Invalid port");
-                kademlia.ping(Node::new(port, Key::new(port.to_string())));
+                if let Err(err) = kademlia.ping(read_peer()) {
+                    println!("Error adding peer: {}", err);
+                }
             }
             Command::Print => {
                 let peers = kademlia.get_all_know_nodes();
@@ -43,13 +68,72 @@ fn main() {
                 }
             }
             Command::BootStrap => {
-                let port = read("Enter port: ").parse().expect("Invalid port");
-                kademlia.bootstrap(Node::new(port, Key::new(port.to_string())));
+                if let Err(err) = kademlia.bootstrap(read_peer()) {
+                    println!("Error bootstrapping: {}", err);
+                }
+            }
+            Command::Put => {
+                let key = Key::new(read("Enter key: "));
+                let value = read("Enter value: ");
+                match kademlia.put(key, value.into_bytes()) {
+                    Ok(stored) => println!("Stored on {} nodes", stored),
+                    Err(err) => println!("Error storing value: {}", err),
+                }
+            }
+            Command::Get => {
+                let key = Key::new(read("Enter key: "));
+                match kademlia.get(&key) {
+                    Ok(Some(value)) => println!("{}", String::from_utf8_lossy(&value)),
+                    Ok(None) => println!("Value not found"),
+                    Err(err) => println!("Error finding value: {}", err),
+                }
             }
         }
     }
 }
 
+struct Args {
+    addresses: AddressConfig,
+    /// Secret key used when there is no saved state, has to match the saved one otherwise
+    identity: PathBuf,
+    state: PathBuf,
+}
+
+/// Usage: node [--bind <addr>] [--external <addr>] [--identity <path>] [--state <path>] [port]
+fn parse_args() -> Args {
+    let mut parsed = Args {
+        addresses: AddressConfig::default(),
+        identity: PathBuf::from("node.key"),
+        state: PathBuf::from("node.state"),
+    };
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bind" => {
+                let addr = args.next().expect("--bind requires an address");
+                parsed.addresses.bind = addr.parse().expect("Invalid bind address");
+            }
+            "--external" => {
+                let addr = args.next().expect("--external requires an address");
+                parsed.addresses.external = Some(addr.parse().expect("Invalid external address"));
+            }
+            "--identity" => {
+                parsed.identity = args.next().expect("--identity requires a path").into();
+            }
+            "--state" => {
+                parsed.state = args.next().expect("--state requires a path").into();
+            }
+            port => parsed
+                .addresses
+                .bind
+                .set_port(port.parse().expect("Invalid port")),
+        }
+    }
+
+    parsed
+}
+
 fn get_command() -> Command {
     loop {
         let input = read(":> ");
@@ -61,6 +145,12 @@ fn get_command() -> Command {
     }
 }
 
+fn read_peer() -> Node {
+    let addr: SocketAddr = read("Enter address: ").parse().expect("Invalid address");
+    let id: Key = read("Enter id: ").parse().expect("Invalid id");
+    Node::new(addr, id)
+}
+
 fn read(message: &str) -> String {
     let mut command = String::new();
     print!("{}", message);
@@ -0,0 +1,448 @@
+//! Node state and protocol rules that don't wait for network, shared by the thread
+//! based [`Kademlia`](crate::Kademlia) and the tokio based one. Front-ends send the
+//! requests asked for here and report what came back
+
+use crate::{
+    config::KademliaConfig,
+    error::{Error, Result},
+    helpers::ExpectLock,
+    identity::Identity,
+    pure,
+    shortlist::Shortlist,
+    state::State,
+    store::ValueStore,
+    table,
+    types::{
+        distance::NodeDistance,
+        key::Key,
+        lookup::{LookupEntry, LookupResult, LookupStats},
+        messages::{FindValueResult, Request, Response, RpcRequest, WriteToken},
+        node::Node,
+    },
+};
+
+use std::{
+    collections::HashSet,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+/// Value with its key and the ttl it is stored with
+type Record = (Key, Vec<u8>, Duration);
+
+pub(crate) struct Protocol {
+    routes: Mutex<table::RoutingTable>,
+    /// Least recently seen nodes currently being pinged before eviction
+    evicting: Mutex<HashSet<Key>>,
+    store: Mutex<ValueStore>,
+    pub identity: Identity,
+    pub node: Node,
+    pub config: KademliaConfig,
+}
+
+impl Protocol {
+    /// Node id has to solve the static puzzle, node is the first entry of its routing table
+    pub fn new(config: KademliaConfig, identity: Identity, node: Node) -> Result<Self> {
+        if !config.puzzle_difficulty.is_static_solved(&node.id) {
+            return Err(Error::PuzzleNotSolved);
+        }
+
+        let mut routes = table::RoutingTable::new(
+            node,
+            config.n_buckets,
+            config.k_param,
+            config.puzzle_difficulty,
+        );
+        routes.update(node);
+
+        Ok(Self {
+            routes: Mutex::new(routes),
+            evicting: Mutex::new(HashSet::new()),
+            store: Mutex::new(ValueStore::new(config.max_store_size)),
+            identity,
+            node,
+            config,
+        })
+    }
+
+    /// Response to request using local routing table and value store. Node that made
+    /// the request is added to known nodes, its id was verified against the signature
+    /// by network interface. Returns node to ping like [`Protocol::update_route`]
+    pub fn answer(&self, request: RpcRequest) -> (Response, Option<Node>) {
+        let least_recent = self.update_route(request.source);
+
+        // answers and write tokens go to the address request came from, so a claimed
+        // source address can't direct responses at another host
+        let requester = Node {
+            addr: request.from,
+            ..request.source
+        };
+        let response = handle_request(
+            request.payload,
+            requester,
+            &self.routes,
+            &self.store,
+            &self.config,
+        );
+
+        (response, least_recent)
+    }
+
+    /// Adds node to the routing table. If its bucket is full, returns least recently seen
+    /// node to ping, it is evicted only if it doesn't respond, freeing the slot for the
+    /// freshest replacement candidate. Node is not returned again until [`Protocol::pinged`]
+    pub fn update_route(&self, node: Node) -> Option<Node> {
+        let update = self.routes.expect_lock().update(node);
+
+        match update {
+            table::Update::Full(least_recent)
+                if self.evicting.expect_lock().insert(least_recent.id) =>
+            {
+                Some(least_recent)
+            }
+            // Already being pinged, new node waits in replacement cache
+            _ => None,
+        }
+    }
+
+    /// Ends liveness check of node returned by [`Protocol::update_route`],
+    /// failed ping already removed it and promoted a replacement
+    pub fn pinged(&self, node: &Node) {
+        self.evicting.expect_lock().remove(&node.id);
+    }
+
+    /// Responding peer is added to routing table with the puzzle nonce it advertised
+    /// in the response, unresponsive one is removed. Returns node to ping like
+    /// [`Protocol::update_route`]
+    pub fn record_liveness<T>(
+        &self,
+        dst: Node,
+        result: Result<(T, Node)>,
+    ) -> (Result<T>, Option<Node>) {
+        match result {
+            Ok((value, source)) => (Ok(value), self.update_route(dst.with_nonce(source.nonce))),
+            Err(err) => {
+                if err.is_peer_failure() {
+                    warn!("Request to peer {}@{} failed: {}", dst.id, dst.addr, err);
+                    self.routes.expect_lock().remove(&dst.id);
+                }
+                (Err(err), None)
+            }
+        }
+    }
+
+    pub fn known_nodes(&self) -> Vec<Node> {
+        self.routes
+            .expect_lock()
+            .get_kbuckets()
+            .iter()
+            .flat_map(|bucket| bucket.nodes.clone())
+            .collect()
+    }
+
+    /// Random key in range of every bucket that had no lookup for refresh interval,
+    /// with index of the bucket
+    pub fn refresh_keys(&self) -> Vec<(usize, Key)> {
+        let stale = {
+            let routes = self.routes.expect_lock();
+            routes.stale_buckets(self.config.refresh_interval)
+        };
+
+        stale
+            .into_iter()
+            .map(|index| {
+                let key_size = self.config.key_space.key_size();
+                (index, pure::random_key_in_bucket(&self.node.id, index, key_size))
+            })
+            .collect()
+    }
+
+    /// Key cut to the key size of the key space, keys given to the node go through this
+    pub fn key(&self, key: &Key) -> Key {
+        self.config.key_space.truncate(key)
+    }
+
+    /// Other node that solves the puzzle, only these are queried by lookups
+    pub fn is_peer(&self, node: &Node) -> bool {
+        node.id != self.node.id && self.config.puzzle_difficulty.is_solved(node)
+    }
+
+    /// k closest known peers to key, bucket of key counts as looked up
+    fn closest_peers(&self, key: &Key) -> Vec<NodeDistance> {
+        let closest = {
+            let mut routes = self.routes.expect_lock();
+            routes.touch(key);
+            routes.get_closest_nodes(key, self.config.k_param)
+        };
+
+        closest
+            .into_iter()
+            .filter(|entry| self.is_peer(&entry.node))
+            .collect()
+    }
+
+    /// Closest known peers dealt to the configured number of disjoint paths,
+    /// each path keeps its own shortlist and a node is queried by at most one path
+    pub fn lookup_paths(&self, id: &Key) -> Vec<Shortlist> {
+        let closest = self.closest_peers(id);
+        let paths = self.config.disjoint_paths;
+
+        (0..paths)
+            .map(|path| {
+                let start = closest.iter().skip(path).step_by(paths).cloned().collect();
+                Shortlist::new(*id, self.config.k_param, start)
+            })
+            .collect()
+    }
+
+    /// Records answer of node to FIND_NODE of lookup path,
+    /// this node and nodes that don't solve the puzzle are never queried
+    pub fn record_nodes(&self, shortlist: &mut Shortlist, node: &Node, result: Result<Vec<Node>>) {
+        match result {
+            Ok(nodes) => {
+                shortlist.responded(node, nodes.into_iter().filter(|node| self.is_peer(node)))
+            }
+            Err(_) => shortlist.failed(node),
+        }
+    }
+
+    /// k closest nodes found by any path, fails if no path found a node
+    pub fn merge_paths(
+        &self,
+        paths: impl IntoIterator<Item = (usize, (Vec<NodeDistance>, LookupStats))>,
+    ) -> Result<LookupResult> {
+        let mut result = LookupResult::default();
+        for (path, (found, stats)) in paths {
+            result.stats.merge(stats);
+            result
+                .nodes
+                .extend(found.into_iter().map(|entry| LookupEntry::new(entry, path)));
+        }
+
+        if result.nodes.is_empty() {
+            return Err(Error::LookupFailed);
+        }
+
+        result.nodes.sort_by_key(|entry| entry.distance);
+        result.nodes.truncate(self.config.k_param);
+
+        Ok(result)
+    }
+
+    pub fn get_value(&self, key: &Key) -> Option<Vec<u8>> {
+        self.store.expect_lock().get(key).cloned()
+    }
+
+    /// Value is stored again every publish interval
+    pub fn publish(&self, key: Key, value: Vec<u8>) {
+        self.store.expect_lock().publish(key, value);
+    }
+
+    pub fn unpublish(&self, key: &Key) {
+        self.store.expect_lock().unpublish(key);
+    }
+
+    /// Key is announced again every publish interval
+    pub fn provide(&self, key: Key) {
+        self.store.expect_lock().provide(key);
+    }
+
+    pub fn stop_providing(&self, key: &Key) {
+        self.store.expect_lock().stop_providing(key);
+    }
+
+    pub fn get_providers(&self, key: &Key) -> Vec<Node> {
+        self.store.expect_lock().get_providers(key)
+    }
+
+    /// Drops expired values and providers. Returns held values due for republishing with
+    /// their remaining ttl, values published by this node due for storing again with
+    /// fresh ttl, and provided keys due for announcing
+    pub fn due_records(&self) -> (Vec<Record>, Vec<Key>) {
+        let mut store = self.store.expect_lock();
+        store.remove_expired();
+
+        let republish = store.due_for_republish(self.config.republish_interval);
+        let publish = store
+            .due_for_publish(self.config.publish_interval)
+            .into_iter()
+            .map(|(key, value)| (key, value, self.config.record_ttl));
+        let provide = store.due_for_provide(self.config.publish_interval);
+
+        (republish.into_iter().chain(publish).collect(), provide)
+    }
+
+    /// Identity and routing table peers to save
+    pub fn state(&self) -> State {
+        State {
+            secret_key: self.identity.secret_key(),
+            peers: self.routes.expect_lock().peers(),
+        }
+    }
+
+    /// Adds restored peers that responded, least recently seen first again
+    /// since pings were answered in random order
+    pub fn restore(&self, mut restored: Vec<(Node, SystemTime)>) {
+        restored.sort_by_key(|(_, last_seen)| *last_seen);
+
+        let mut routes = self.routes.expect_lock();
+        for (node, _) in &restored {
+            routes.update(*node);
+        }
+
+        info!("Restored {} peers", restored.len());
+    }
+}
+
+/// Iterative value lookup, stops after the round in which any node returned the value.
+/// Local store was already checked, this node is never queried
+pub(crate) struct ValueLookup {
+    shortlist: Shortlist,
+    alpha: usize,
+    record_ttl: Duration,
+    found: Option<Vec<u8>>,
+    responded: bool,
+}
+
+impl ValueLookup {
+    pub fn new(protocol: &Protocol, key: &Key) -> Self {
+        let config = &protocol.config;
+        let start = protocol.closest_peers(key);
+
+        Self {
+            shortlist: Shortlist::new(*key, config.k_param, start),
+            alpha: config.alpha,
+            record_ttl: config.record_ttl,
+            found: None,
+            responded: false,
+        }
+    }
+
+    /// Nodes to query in next round, empty when lookup is finished
+    pub fn next_round(&mut self) -> Vec<Node> {
+        if self.found.is_some() {
+            return vec![];
+        }
+
+        self.responded |= self.shortlist.has_responded();
+        self.shortlist.next_round(self.alpha, |_| true)
+    }
+
+    /// Records answer of node to FIND_VALUE, see [`Protocol::record_nodes`]
+    pub fn record(&mut self, protocol: &Protocol, node: &Node, result: Result<FindValueResult>) {
+        match result {
+            Ok(FindValueResult::Value(value)) => self.found = Some(value),
+            Ok(FindValueResult::Nodes(nodes)) => {
+                protocol.record_nodes(&mut self.shortlist, node, Ok(nodes))
+            }
+            Err(err) => protocol.record_nodes(&mut self.shortlist, node, Err(err)),
+        }
+    }
+
+    /// Closest node that responded without the found value, with ttl of the copy to
+    /// cache there. Ttl is halved once and again for every observed node closer to key,
+    /// so a cached copy always expires before the records it was copied from
+    pub fn cache_at(&self) -> Option<(Node, Vec<u8>, Duration)> {
+        let value = self.found.clone()?;
+        let (node, closer) = self.shortlist.closest_responded()?;
+
+        Some((node, value, pure::cache_ttl(self.record_ttl, closer + 1)))
+    }
+
+    /// Found value, none if no node had it. Fails if no node responded
+    pub fn finish(self) -> Result<Option<Vec<u8>>> {
+        if self.found.is_some() || self.responded || self.shortlist.has_responded() {
+            Ok(self.found)
+        } else {
+            Err(Error::LookupFailed)
+        }
+    }
+}
+
+/// Answer taken out of response by `answer`, responses of other kinds are unexpected.
+/// Refused requests are errors, only peers of other version or network are removed
+/// from routing table
+pub(crate) fn expect<T>(
+    (response, source): (Response, Node),
+    answer: impl FnOnce(Response) -> Option<T>,
+) -> Result<(T, Node)> {
+    match response {
+        Response::Rejected(reason) => Err(Error::Rejected(reason)),
+        Response::Incompatible(version, network_id) => {
+            Err(Error::Incompatible(version, network_id))
+        }
+        response => answer(response)
+            .map(|answer| (answer, source))
+            .ok_or(Error::UnexpectedResponse),
+    }
+}
+
+/// Providers found locally and at other nodes, each once.
+/// Providers learned over BEP 5 have no id, so they are told apart by address
+pub(crate) fn unique_providers(mut providers: Vec<Node>) -> Vec<Node> {
+    let mut seen = HashSet::new();
+    providers.retain(|provider| seen.insert(provider.addr));
+    providers
+}
+
+/// Answer to request from source using local routing table and value store
+pub(crate) fn handle_request(
+    request: Request,
+    source: Node,
+    routes: &Mutex<table::RoutingTable>,
+    store: &Mutex<ValueStore>,
+    config: &KademliaConfig,
+) -> Response {
+    match request {
+        Request::Ping => Response::Pong,
+        Request::FindNode(ref id) => {
+            let routes = routes.expect_lock();
+            let result = routes.get_closest_nodes(id, config.k_param);
+            Response::FindNode(result.into_iter().map(|entry| entry.node).collect())
+        }
+        Request::Store(key, value, ttl, cached) => {
+            let ttl = ttl.min(config.record_ttl);
+            let mut store = store.expect_lock();
+            let stored = if cached {
+                store.cache(key, value, ttl)
+            } else {
+                store.insert(key, value, ttl)
+            };
+            if !stored {
+                return Response::Rejected("Store is full".to_owned());
+            }
+            Response::Store
+        }
+        Request::FindValue(ref key) => {
+            if let Some(value) = store.expect_lock().get(key) {
+                Response::FindValue(*key, FindValueResult::Value(value.clone()))
+            } else {
+                let routes = routes.expect_lock();
+                let result = routes.get_closest_nodes(key, config.k_param);
+                let nodes = result.into_iter().map(|entry| entry.node).collect();
+                Response::FindValue(*key, FindValueResult::Nodes(nodes))
+            }
+        }
+        Request::AddProvider(key, ref token) => {
+            let mut store = store.expect_lock();
+            let authorized = match token {
+                WriteToken::Issued(token) => store.check_token(source.addr.ip(), token),
+                WriteToken::Transport => true,
+            };
+            if !authorized {
+                return Response::Rejected("Invalid write token".to_owned());
+            }
+
+            store.add_provider(key, source, config.record_ttl);
+            Response::AddProvider
+        }
+        Request::GetProviders(ref key) => {
+            let closest = routes.expect_lock().get_closest_nodes(key, config.k_param);
+            let nodes = closest.into_iter().map(|entry| entry.node).collect();
+
+            let mut store = store.expect_lock();
+            let token = store.issue_token(source.addr.ip());
+            Response::GetProviders(store.get_providers(key), nodes, token)
+        }
+    }
+}
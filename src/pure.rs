@@ -1,5 +1,8 @@
 // use num_bigint::BigUint;
 
+use rand::Rng;
+use std::time::Duration;
+
 use crate::{types::key::Key, KEY_SIZE};
 
 // uint::construct_uint! {
@@ -41,6 +44,54 @@ pub fn bucket_index(local: &Key, key: &Key) -> usize {
     KEY_SIZE * 8 - 1
 }
 
+/// Random key of `key_size` bytes that falls into bucket with given index,
+/// inverse of [`bucket_index`]
+pub fn random_key_in_bucket(local: &Key, index: usize, key_size: usize) -> Key {
+    let mut rng = rand::thread_rng();
+    let (byte, bit) = (index / 8, 7 - (index % 8) as u32);
+
+    let mut distance = [0u8; KEY_SIZE];
+    distance[byte] = rng.gen::<u8>().checked_shl(bit + 1).unwrap_or(0) | (1 << bit);
+    rng.fill(&mut distance[byte + 1..key_size]);
+
+    let mut key = *local;
+    for (k, d) in key.0.iter_mut().zip(distance) {
+        *k ^= d;
+    }
+    key
+}
+
+/// Ttl of value cached at a node with `between` nodes between it and the
+/// node closest to the key, exponentially shorter the farther the node is
+pub fn cache_ttl(record_ttl: Duration, between: usize) -> Duration {
+    let divisor = u32::try_from(between)
+        .ok()
+        .and_then(|between| 1u32.checked_shl(between))
+        .unwrap_or(u32::MAX);
+
+    record_ttl / divisor
+}
+
+#[test]
+fn cache_ttl_test() {
+    let ttl = Duration::from_secs(64);
+
+    assert_eq!(cache_ttl(ttl, 0), ttl);
+    assert_eq!(cache_ttl(ttl, 1), Duration::from_secs(32));
+    assert_eq!(cache_ttl(ttl, 6), Duration::from_secs(1));
+    assert!(cache_ttl(ttl, 1000) < Duration::from_micros(1));
+}
+
+#[test]
+fn random_key_in_bucket_test() {
+    let local = Key::new("local".to_owned());
+
+    for index in 0..KEY_SIZE * 8 {
+        let key = random_key_in_bucket(&local, index, KEY_SIZE);
+        assert_eq!(bucket_index(&local, &key), index);
+    }
+}
+
 #[test]
 fn bucket_index_test() {
     // use rand::Rng;
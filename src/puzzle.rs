@@ -0,0 +1,128 @@
+//! S/Kademlia crypto puzzles, they make generating many node ids expensive.
+//!
+//! Static puzzle: hash of node id has to start with `static_bits` zero bits,
+//! it is solved once when the keypair is generated.
+//! Dynamic puzzle: hash of node id xor nonce has to start with `dynamic_bits`
+//! zero bits, nonce is advertised together with the node.
+
+use crate::{
+    error::{Error, Result},
+    types::{key::Key, node::Node},
+    KEY_SIZE,
+};
+
+use rand::{rngs::OsRng, Rng};
+use sha2::{Digest, Sha256};
+
+/// Number of leading zero bits required by the puzzles, 0 disables the puzzle
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PuzzleDifficulty {
+    pub static_bits: u32,
+    pub dynamic_bits: u32,
+}
+
+impl PuzzleDifficulty {
+    /// Hardest puzzle solved, takes seconds. Every extra bit doubles expected time
+    pub const MAX_BITS: u32 = 24;
+
+    /// Bits are clamped to [`MAX_BITS`](Self::MAX_BITS)
+    pub fn new(static_bits: u32, dynamic_bits: u32) -> Self {
+        Self {
+            static_bits: static_bits.min(Self::MAX_BITS),
+            dynamic_bits: dynamic_bits.min(Self::MAX_BITS),
+        }
+    }
+
+    /// Node is admitted only if its id and nonce solve both puzzles
+    pub fn is_solved(&self, node: &Node) -> bool {
+        self.is_static_solved(&node.id) && self.is_dynamic_solved(&node.id, &node.nonce)
+    }
+
+    pub fn is_static_solved(&self, id: &Key) -> bool {
+        leading_zero_bits(&Sha256::digest(id.0)) >= self.static_bits
+    }
+
+    pub fn is_dynamic_solved(&self, id: &Key, nonce: &Key) -> bool {
+        leading_zero_bits(&Sha256::digest(xor(id, nonce))) >= self.dynamic_bits
+    }
+
+    /// Searches for nonce that solves dynamic puzzle for id, counting up from a
+    /// random nonce. Fails for puzzles harder than [`MAX_BITS`](Self::MAX_BITS)
+    pub fn solve_dynamic(&self, id: &Key) -> Result<Key> {
+        if self.dynamic_bits > Self::MAX_BITS {
+            return Err(Error::PuzzleTooHard(self.dynamic_bits));
+        }
+
+        let mut nonce = Key([0; KEY_SIZE]);
+        OsRng.fill(&mut nonce.0);
+
+        while !self.is_dynamic_solved(id, &nonce) {
+            increment(&mut nonce.0);
+        }
+
+        Ok(nonce)
+    }
+}
+
+/// Big endian increment that wraps around
+fn increment(bytes: &mut [u8]) {
+    for byte in bytes.iter_mut().rev() {
+        let (sum, overflow) = byte.overflowing_add(1);
+        *byte = sum;
+        if !overflow {
+            break;
+        }
+    }
+}
+
+fn xor(a: &Key, b: &Key) -> [u8; KEY_SIZE] {
+    let mut result = a.0;
+    for (r, b) in result.iter_mut().zip(b.0) {
+        *r ^= b;
+    }
+    result
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+#[test]
+fn puzzle_test() {
+    let id = Key::new("puzzle".to_owned());
+    let difficulty = PuzzleDifficulty::new(0, 8);
+
+    assert!(PuzzleDifficulty::default().is_solved(&Node::new(([0, 0, 0, 0], 0).into(), id)));
+
+    let nonce = difficulty.solve_dynamic(&id).unwrap();
+    assert!(difficulty.is_dynamic_solved(&id, &nonce));
+
+    assert_eq!(
+        PuzzleDifficulty::new(0, 256).dynamic_bits,
+        PuzzleDifficulty::MAX_BITS
+    );
+    let too_hard = PuzzleDifficulty {
+        static_bits: 0,
+        dynamic_bits: 64,
+    };
+    assert!(matches!(
+        too_hard.solve_dynamic(&id),
+        Err(Error::PuzzleTooHard(64))
+    ));
+
+    let mut bytes = [0x00, 0xFF, 0xFF];
+    increment(&mut bytes);
+    assert_eq!(bytes, [0x01, 0x00, 0x00]);
+
+    let mut hash = [0xFF; KEY_SIZE];
+    hash[0] = 0;
+    hash[1] = 0b0001_0000;
+    assert_eq!(leading_zero_bits(&hash), 11);
+}
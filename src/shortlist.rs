@@ -0,0 +1,201 @@
+use crate::types::{distance::NodeDistance, key::Key, lookup::LookupStats, node::Node};
+
+use std::collections::HashSet;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Waiting,
+    Queried,
+    Responded,
+    Failed,
+}
+
+struct Candidate {
+    entry: NodeDistance,
+    state: State,
+}
+
+/// Candidates of an iterative lookup with their query state, the k closest ones
+/// that didn't fail take part in the lookup. Rounds query `alpha` closest waiting
+/// candidates among them, closer nodes found in a round become new candidates and
+/// a failed candidate is replaced by the next closest one. Lookup is done when a
+/// round found no closer node and all k closest were queried, that is when there
+/// is no waiting candidate among them left
+pub(crate) struct Shortlist {
+    target: Key,
+    k: usize,
+    /// Sorted by distance to target, including failed ones and those beyond k
+    candidates: Vec<Candidate>,
+    /// Every node that was ever a candidate, failed nodes are not added again
+    known: HashSet<Key>,
+    stats: LookupStats,
+}
+
+impl Shortlist {
+    pub fn new(target: Key, k: usize, start: Vec<NodeDistance>) -> Self {
+        let mut shortlist = Self {
+            target,
+            k,
+            candidates: vec![],
+            known: HashSet::new(),
+            stats: LookupStats::default(),
+        };
+
+        shortlist.add(start.into_iter().map(|entry| entry.node));
+        shortlist
+    }
+
+    /// Nodes to query in next round, empty when lookup is finished.
+    /// Nodes for which `claim` returns false are dropped, they are queried elsewhere
+    pub fn next_round(&mut self, alpha: usize, mut claim: impl FnMut(&Node) -> bool) -> Vec<Node> {
+        let mut batch = vec![];
+        let mut active = 0;
+        let k = self.k;
+
+        self.candidates.retain_mut(|candidate| {
+            if active == k || candidate.state == State::Failed {
+                return true;
+            }
+
+            if batch.len() == alpha || candidate.state != State::Waiting {
+                active += 1;
+                return true;
+            }
+
+            if !claim(&candidate.entry.node) {
+                return false;
+            }
+
+            candidate.state = State::Queried;
+            batch.push(candidate.entry.node);
+            active += 1;
+            true
+        });
+
+        if !batch.is_empty() {
+            self.stats.rounds += 1;
+            self.stats.rpcs_sent += batch.len();
+        }
+
+        batch
+    }
+
+    pub fn responded(&mut self, node: &Node, nodes: impl IntoIterator<Item = Node>) {
+        if let Some(candidate) = self.candidate(node) {
+            candidate.state = State::Responded;
+        }
+
+        self.add(nodes);
+    }
+
+    /// Failed candidate stays in the list, so the next closest one takes its place
+    pub fn failed(&mut self, node: &Node) {
+        self.stats.failures += 1;
+        if let Some(candidate) = self.candidate(node) {
+            candidate.state = State::Failed;
+        }
+    }
+
+    pub fn has_responded(&self) -> bool {
+        self.active()
+            .any(|candidate| candidate.state == State::Responded)
+    }
+
+    /// Closest candidate that responded with number of candidates closer to target
+    pub fn closest_responded(&self) -> Option<(Node, usize)> {
+        self.active()
+            .enumerate()
+            .find(|(_, candidate)| candidate.state == State::Responded)
+            .map(|(index, candidate)| (candidate.entry.node, index))
+    }
+
+    /// Candidates among the k closest that responded, closest first
+    pub fn finish(self) -> (Vec<NodeDistance>, LookupStats) {
+        let responded = self
+            .active()
+            .filter(|candidate| candidate.state == State::Responded)
+            .map(|candidate| candidate.entry.clone())
+            .collect();
+
+        (responded, self.stats)
+    }
+
+    /// k closest candidates that didn't fail
+    fn active(&self) -> impl Iterator<Item = &Candidate> {
+        self.candidates
+            .iter()
+            .filter(|candidate| candidate.state != State::Failed)
+            .take(self.k)
+    }
+
+    fn candidate(&mut self, node: &Node) -> Option<&mut Candidate> {
+        self.candidates
+            .iter_mut()
+            .find(|candidate| candidate.entry.node.id == node.id)
+    }
+
+    /// Responses carry only nodes, distance to target is computed here
+    fn add(&mut self, nodes: impl IntoIterator<Item = Node>) {
+        for node in nodes {
+            if self.known.insert(node.id) {
+                let distance = node.id.distance(&self.target);
+                self.candidates.push(Candidate {
+                    entry: NodeDistance::new(node, distance),
+                    state: State::Waiting,
+                });
+            }
+        }
+
+        self.candidates
+            .sort_by_key(|candidate| candidate.entry.distance);
+    }
+}
+
+#[test]
+fn shortlist_test() {
+    use crate::KEY_SIZE;
+    use std::net::SocketAddr;
+
+    let entry = |byte: u8| {
+        let id = Key([byte; KEY_SIZE]);
+        let node = Node::new(SocketAddr::from(([127, 0, 0, 1], byte as u16)), id);
+        NodeDistance::new(node, id.distance(&Key([0; KEY_SIZE])))
+    };
+
+    let mut shortlist = Shortlist::new(Key([0; KEY_SIZE]), 3, vec![entry(8), entry(9)]);
+
+    let batch = shortlist.next_round(1, |_| true);
+    assert_eq!(batch, vec![entry(8).node]);
+    shortlist.responded(&batch[0], [4, 5, 6].map(|byte| entry(byte).node));
+
+    // closer nodes were found and 8 was pushed beyond the k closest
+    let batch = shortlist.next_round(1, |_| true);
+    assert_eq!(batch, vec![entry(4).node]);
+    shortlist.responded(&batch[0], vec![entry(9).node]);
+
+    // 6 was queried on another path
+    let batch = shortlist.next_round(2, |node| node.id != entry(6).node.id);
+    assert_eq!(batch, vec![entry(5).node]);
+    shortlist.failed(&batch[0]);
+
+    // 8 takes the place of failed 5, and 9 becomes one of the k closest
+    let batch = shortlist.next_round(1, |_| true);
+    assert_eq!(batch, vec![entry(9).node]);
+    shortlist.responded(&batch[0], vec![entry(5).node]);
+
+    assert!(shortlist.next_round(1, |_| true).is_empty());
+
+    assert!(shortlist.has_responded());
+    assert_eq!(shortlist.closest_responded(), Some((entry(4).node, 0)));
+
+    let (nodes, stats) = shortlist.finish();
+    assert_eq!(nodes, vec![entry(4), entry(8), entry(9)]);
+    assert_eq!(
+        stats,
+        LookupStats {
+            rounds: 4,
+            rpcs_sent: 4,
+            failures: 1
+        }
+    );
+}
@@ -1,125 +1,327 @@
 use crate::{
+    codec,
+    config::KademliaConfig,
+    error::{Error, Result},
     helpers::ExpectLock,
+    identity::Identity,
+    transport::Transport,
     types::{
-        messages::{Message, Request, Response, RpcMessage, RpcRequest},
+        key::{Key, KeySpace},
+        messages::{FindValueResult, Message, Request, Response, RpcMessage, RpcRequest},
         node::Node,
     },
+    KEY_SIZE,
 };
 
-use rand::{thread_rng, Rng};
+use rand::{rngs::OsRng, Rng};
 use std::{
     collections::HashMap,
-    net::UdpSocket,
-    sync::{mpsc, Arc, Mutex},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc, Mutex,
+    },
     thread,
     time::Duration,
 };
 
+/// Completes request with response and the node that sent it,
+/// or with `None` when request timed out
+type ResponseSender = mpsc::Sender<Option<(Response, Node)>>;
+
 #[derive(Clone)]
 pub struct NetworkInterface {
-    socket: Arc<UdpSocket>,
-    in_progress: Arc<Mutex<HashMap<usize, mpsc::Sender<Option<Response>>>>>,
+    transport: Arc<dyn Transport>,
+    pending: Arc<PendingRequests<ResponseSender>>,
+    identity: Identity,
     node: Node,
+    request_timeout: Duration,
+    allow_unsigned: bool,
+    key_space: KeySpace,
+    mtu: usize,
 }
 
 impl NetworkInterface {
-    pub fn new(node: Node) -> Self {
-        let socket = UdpSocket::bind(node.get_addr()).expect("Error binding");
+    pub fn new(
+        config: &KademliaConfig,
+        identity: Identity,
+        transport: Arc<dyn Transport>,
+    ) -> Result<Self> {
+        let external = config.addresses.external;
+        let id = config.key_space.node_id(&identity.public_key());
+        let nonce = config.puzzle_difficulty.solve_dynamic(&id)?;
+        let node = Node::new(external.unwrap_or(transport.local_addr()?), id).with_nonce(nonce);
 
-        Self {
-            socket: Arc::new(socket),
-            in_progress: Arc::new(Mutex::new(HashMap::new())),
+        Ok(Self {
+            transport,
+            pending: Arc::new(PendingRequests::default()),
+            identity,
             node,
-        }
+            request_timeout: config.request_timeout,
+            allow_unsigned: config.allow_unsigned,
+            key_space: config.key_space,
+            mtu: config.mtu,
+        })
     }
 
     /// this should be moved (not handled by kademlia)
     pub fn spawn(self, sender: mpsc::Sender<RpcRequest>) {
         thread::spawn(move || {
-            let mut buf = [0u8; 4096]; // somewhere
-
-            loop {
-                let (len, _) = self
-                    .socket
-                    .recv_from(&mut buf)
-                    .expect("Error reading from socket");
-
-                let RpcMessage {
-                    token,
-                    source,
-                    message,
-                    ..
-                } = RpcMessage::from_bytes(&buf[..len]);
-
-                match message {
-                    Message::Request(request) => {
-                        let wrapped_req = RpcRequest {
-                            token,
-                            source,
-                            payload: request,
-                        };
-
-                        if sender.send(wrapped_req).is_err() {
-                            error!("Unable to use channel");
-                            break;
-                        }
-                    }
-                    Message::Response(response) => {
-                        let socket = self.clone();
-                        thread::spawn(move || {
-                            let mut pending = socket.in_progress.expect_lock();
-
-                            let Some(sender) = pending.get(&token) else {
-                                warn!("Received invalid token"); // this will also happen if response is longer than timeout
-                                return;
-                            };
-
-                            // Handle failed here
-                            if sender.send(Some(response)).is_ok() {
-                                pending.remove(&token);
-                            }
-                        });
-                    }
-                }
-            }
+            self.pending.receive(
+                &*self.transport,
+                self.allow_unsigned,
+                self.key_space,
+                |request| sender.send(request).is_ok(),
+                |waiting, response| {
+                    let _ = waiting.send(Some(response));
+                },
+            )
         });
     }
 
-    pub fn send_msg(&self, msg: RpcMessage, destination: u16) {
-        let encoded = msg.to_bytes();
-        self.socket
-            .send_to(&encoded, format!("{}:{}", env!("IP_ADDR"), destination))
-            .expect("Error sending");
+    pub fn node(&self) -> Node {
+        self.node
     }
 
-    pub fn request(&self, request: Request, destination: Node) -> mpsc::Receiver<Option<Response>> {
-        let (sender, receiver) = mpsc::channel(); // this should be oneshot channel with timeout handled properly
+    pub fn rejected_responses(&self) -> u64 {
+        self.pending.rejected()
+    }
 
-        let mut pending = self.in_progress.expect_lock();
+    /// Signs message as this node and sends it, node lists are shortened to fit in one datagram
+    pub fn send_msg(&self, token: u128, message: Message, destination: SocketAddr) -> Result<()> {
+        let msg = sign_within_mtu(token, self.node, message, &self.identity, self.mtu)?;
+        self.transport.send(&msg, destination)
+    }
 
-        let mut rng = thread_rng();
-        let token = rng.gen_range(0..10000_usize);
+    /// Receiver gets response with the node that sent it,
+    /// or `None` if there is no response before request timeout
+    pub fn request(
+        &self,
+        request: Request,
+        destination: Node,
+    ) -> Result<mpsc::Receiver<Option<(Response, Node)>>> {
+        let (sender, receiver) = mpsc::channel(); // this should be oneshot channel with timeout handled properly
 
-        pending.insert(token, sender.clone());
+        let token = self.pending.insert(destination, sender.clone());
 
-        self.send_msg(
-            RpcMessage {
-                token,
-                source: self.node.port,
-                message: Message::Request(request),
-            },
-            destination.port,
-        );
+        let sent = self.send_msg(token, Message::Request(request), destination.addr);
+
+        if let Err(err) = sent {
+            self.pending.remove(&token);
+            return Err(err);
+        }
 
         let rpc = self.clone();
         thread::spawn(move || {
-            thread::sleep(Duration::from_secs(1)); // Time to wait for response
-            if sender.send(None).is_ok() {
-                error!("Unable to send message");
-            }
-            rpc.in_progress.expect_lock().remove(&token);
+            thread::sleep(rpc.request_timeout); // Time to wait for response
+
+            // fails if response was already received and receiver dropped
+            let _ = sender.send(None);
+            rpc.pending.remove(&token);
         });
 
-        receiver
+        Ok(receiver)
+    }
+}
+
+/// Request waiting for response from `destination`
+struct PendingRequest<S> {
+    sender: S,
+    destination: Node,
+}
+
+/// Requests waiting for responses by token, `S` completes a request
+pub(crate) struct PendingRequests<S> {
+    requests: Mutex<HashMap<u128, PendingRequest<S>>>,
+    /// Responses dropped because of invalid signature or unexpected sender
+    rejected: AtomicU64,
+}
+
+impl<S> Default for PendingRequests<S> {
+    fn default() -> Self {
+        Self {
+            requests: Mutex::new(HashMap::new()),
+            rejected: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<S> PendingRequests<S> {
+    /// Random token of new request to destination
+    pub fn insert(&self, destination: Node, sender: S) -> u128 {
+        let token = OsRng.gen::<u128>();
+        let pending = PendingRequest {
+            sender,
+            destination,
+        };
+        self.requests.expect_lock().insert(token, pending);
+        token
+    }
+
+    pub fn remove(&self, token: &u128) {
+        self.requests.expect_lock().remove(token);
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Reads messages from transport until it disconnects or `request` returns false.
+    /// Requests go to `request`, responses are given to `respond` with the sender of
+    /// the request they complete
+    pub fn receive(
+        &self,
+        transport: &dyn Transport,
+        allow_unsigned: bool,
+        key_space: KeySpace,
+        mut request: impl FnMut(RpcRequest) -> bool,
+        mut respond: impl FnMut(S, (Response, Node)),
+    ) {
+        loop {
+            let (msg, from) = match transport.recv() {
+                Ok(received) => received,
+                Err(Error::Disconnected) => break,
+                Err(err) if err.is_invalid_message() => {
+                    warn!("Dropping message: {}", err);
+                    continue;
+                }
+                Err(err) => {
+                    error!("Error receiving message: {}", err);
+                    continue;
+                }
+            };
+
+            if let Err(err) = check_signature(&msg, allow_unsigned, key_space) {
+                warn!("Dropping message from {}: {}", from, err);
+                if matches!(msg.message, Message::Response(_)) {
+                    self.rejected.fetch_add(1, Ordering::Relaxed);
+                }
+                continue;
+            }
+
+            let RpcMessage {
+                token,
+                mut source,
+                mut message,
+                ..
+            } = msg;
+
+            resolve_source(&mut source, &mut message, from);
+
+            match message {
+                Message::Request(payload) => {
+                    let wrapped_req = RpcRequest {
+                        token,
+                        source,
+                        from,
+                        payload,
+                    };
+
+                    if !request(wrapped_req) {
+                        error!("Unable to use channel");
+                        break;
+                    }
+                }
+                Message::Response(response) => {
+                    let mut pending = self.requests.expect_lock();
+
+                    let Some(request) = pending.get(&token) else {
+                        warn!("Received invalid token"); // this will also happen if response is longer than timeout
+                        continue;
+                    };
+
+                    identify_rejection(&request.destination, &mut source, &response);
+                    if !is_expected_source(&request.destination, &source, from) {
+                        warn!("Rejected response from {}@{}", source.id, from);
+                        self.rejected.fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    }
+
+                    if let Some(request) = pending.remove(&token) {
+                        respond(request.sender, (response, source));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Message signed as node, node lists are shortened so it fits in one datagram
+pub(crate) fn sign_within_mtu(
+    token: u128,
+    node: Node,
+    message: Message,
+    identity: &Identity,
+    mtu: usize,
+) -> Result<RpcMessage> {
+    let max_size = mtu.saturating_sub(codec::HEADER_SIZE);
+    RpcMessage::signed_within(token, node, message, identity, max_size)
+}
+
+/// Signed messages have to verify with node id of the key space, unsigned ones pass
+/// only if configuration allows them or they are rejections without node id that
+/// wire formats can't sign
+pub(crate) fn check_signature(
+    msg: &RpcMessage,
+    allow_unsigned: bool,
+    key_space: KeySpace,
+) -> Result<()> {
+    let anonymous = is_anonymous_rejection(&msg.source, &msg.message);
+    if (allow_unsigned || anonymous) && !msg.is_signed() {
+        return Ok(());
+    }
+
+    msg.verify()?;
+    match msg.public_key {
+        Some(public_key) if key_space.node_id(&public_key) == msg.source.id => Ok(()),
+        _ => Err(Error::InvalidSignature),
+    }
+}
+
+/// Response is accepted only from the address and node id request was sent to
+pub(crate) fn is_expected_source(destination: &Node, source: &Node, from: SocketAddr) -> bool {
+    let addr = destination.addr;
+    // node bound to all interfaces answers from whichever address request reached
+    let addr_matches = addr == from || (addr.ip().is_unspecified() && addr.port() == from.port());
+
+    addr_matches && destination.id == source.id
+}
+
+/// KRPC errors and native rejections of incompatible messages carry no node id, a
+/// rejection with zeroed id is taken to come from the node request was sent to,
+/// its address is still checked
+pub(crate) fn identify_rejection(destination: &Node, source: &mut Node, response: &Response) {
+    if is_rejection(response) && source.id == Key([0; KEY_SIZE]) {
+        source.id = destination.id;
+    }
+}
+
+fn is_anonymous_rejection(source: &Node, message: &Message) -> bool {
+    matches!(message, Message::Response(response) if is_rejection(response))
+        && source.id == Key([0; KEY_SIZE])
+}
+
+fn is_rejection(response: &Response) -> bool {
+    matches!(response, Response::Rejected(_) | Response::Incompatible(..))
+}
+
+/// Peers bound to all interfaces without an external address advertise an
+/// unspecified ip, replace it with the ip the datagram actually came from
+pub(crate) fn resolve_source(source: &mut Node, message: &mut Message, from: SocketAddr) {
+    if !source.addr.ip().is_unspecified() {
+        return;
+    }
+
+    source.addr.set_ip(from.ip());
+
+    let nodes = match message {
+        Message::Response(Response::FindNode(nodes)) => nodes,
+        Message::Response(Response::FindValue(_, FindValueResult::Nodes(nodes))) => nodes,
+        Message::Response(Response::GetProviders(_, nodes, _)) => nodes,
+        _ => return,
+    };
+
+    for node in nodes.iter_mut().filter(|node| node.id == source.id) {
+        node.addr = source.addr;
     }
 }
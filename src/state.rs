@@ -0,0 +1,23 @@
+use crate::{error::Result, helpers, types::node::Node};
+
+use ed25519_dalek::SecretKey;
+use std::{fs, path::Path, time::SystemTime};
+
+/// Snapshot of a node written by [`Kademlia::save_state`](crate::Kademlia::save_state),
+/// contains secret key so it is readable only by owner
+#[derive(Serialize, Deserialize)]
+pub(crate) struct State {
+    pub secret_key: SecretKey,
+    /// Routing table peers with the time they were last seen
+    pub peers: Vec<(Node, SystemTime)>,
+}
+
+impl State {
+    pub fn save(&self, path: &Path) -> Result<()> {
+        Ok(helpers::write_private(path, &bincode::serialize(self)?)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(bincode::deserialize(&fs::read(path)?)?)
+    }
+}
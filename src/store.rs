@@ -0,0 +1,339 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    token::WriteTokens,
+    types::{key::Key, node::Node},
+    KEY_SIZE,
+};
+
+/// Most providers kept for one key, the ones closest to expiry are dropped first
+const MAX_PROVIDERS: usize = 64;
+
+/// Value held on behalf of the network
+#[derive(Debug)]
+struct Record {
+    value: Vec<u8>,
+    expires: Instant,
+    /// Last time record was stored by a peer or republished by this node
+    stored: Instant,
+    /// Copy cached on lookup path, expires without being republished
+    cached: bool,
+}
+
+/// Value this node published with [`Kademlia::put`](crate::Kademlia::put)
+#[derive(Debug)]
+struct Published {
+    value: Vec<u8>,
+    at: Instant,
+}
+
+/// Node that announced it holds content for a key
+#[derive(Debug)]
+struct Provider {
+    node: Node,
+    expires: Instant,
+}
+
+/// Values and provider records this node holds on behalf of the network,
+/// values it originally published and keys it provides
+#[derive(Debug, Default)]
+pub struct ValueStore {
+    values: HashMap<Key, Record>,
+    /// Size of held values with their keys
+    size: usize,
+    max_size: usize,
+    published: HashMap<Key, Published>,
+    providers: HashMap<Key, Vec<Provider>>,
+    /// Keys this node provides with the time they were last announced
+    providing: HashMap<Key, Instant>,
+    tokens: WriteTokens,
+}
+
+impl ValueStore {
+    /// Held values with their keys take at most `max_size` bytes
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            max_size,
+            ..Self::default()
+        }
+    }
+
+    /// Stores value that expires after ttl, false if it doesn't fit
+    pub fn insert(&mut self, key: Key, value: Vec<u8>, ttl: Duration) -> bool {
+        self.put(key, value, ttl, false)
+    }
+
+    /// Stores cached copy that expires after ttl, held record of key is kept instead.
+    /// False if it doesn't fit
+    pub fn cache(&mut self, key: Key, value: Vec<u8>, ttl: Duration) -> bool {
+        if self.values.get(&key).is_some_and(|record| !record.cached) {
+            return true;
+        }
+
+        self.put(key, value, ttl, true)
+    }
+
+    /// Replaced record of key makes room, expired ones are dropped if there is none
+    fn put(&mut self, key: Key, value: Vec<u8>, ttl: Duration, cached: bool) -> bool {
+        if !self.fits(&key, &value) {
+            self.remove_expired();
+            if !self.fits(&key, &value) {
+                return false;
+            }
+        }
+
+        let now = Instant::now();
+        let record = Record {
+            value,
+            expires: now + ttl,
+            stored: now,
+            cached,
+        };
+
+        self.size += record_size(&record);
+        if let Some(replaced) = self.values.insert(key, record) {
+            self.size -= record_size(&replaced);
+        }
+        true
+    }
+
+    fn fits(&self, key: &Key, value: &[u8]) -> bool {
+        let replaced = self.values.get(key).map_or(0, record_size);
+        self.size - replaced + KEY_SIZE + value.len() <= self.max_size
+    }
+
+    pub fn get(&self, key: &Key) -> Option<&Vec<u8>> {
+        self.values
+            .get(key)
+            .filter(|record| record.expires > Instant::now())
+            .map(|record| &record.value)
+    }
+
+    /// Remembers value so it can be stored again every publish interval
+    pub fn publish(&mut self, key: Key, value: Vec<u8>) {
+        let published = Published {
+            value,
+            at: Instant::now(),
+        };
+
+        self.published.insert(key, published);
+    }
+
+    /// Forgets published value, it is no longer stored again
+    pub fn unpublish(&mut self, key: &Key) {
+        self.published.remove(key);
+    }
+
+    /// Adds provider for key or extends its expiry
+    pub fn add_provider(&mut self, key: Key, node: Node, ttl: Duration) {
+        let providers = self.providers.entry(key).or_default();
+        providers.retain(|provider| provider.node.id != node.id);
+
+        if providers.len() >= MAX_PROVIDERS {
+            providers.sort_by_key(|provider| provider.expires);
+            providers.remove(0);
+        }
+
+        providers.push(Provider {
+            node,
+            expires: Instant::now() + ttl,
+        });
+    }
+
+    pub fn get_providers(&self, key: &Key) -> Vec<Node> {
+        let now = Instant::now();
+
+        self.providers
+            .get(key)
+            .into_iter()
+            .flatten()
+            .filter(|provider| provider.expires > now)
+            .map(|provider| provider.node)
+            .collect()
+    }
+
+    /// Token node at ip has to present to be added as provider
+    pub fn issue_token(&mut self, ip: IpAddr) -> Vec<u8> {
+        self.tokens.issue(ip)
+    }
+
+    pub fn check_token(&mut self, ip: IpAddr, token: &[u8]) -> bool {
+        self.tokens.check(ip, token)
+    }
+
+    /// Remembers key so it can be announced again every publish interval
+    pub fn provide(&mut self, key: Key) {
+        self.providing.insert(key, Instant::now());
+    }
+
+    /// Forgets provided key, it is no longer announced again
+    pub fn stop_providing(&mut self, key: &Key) {
+        self.providing.remove(key);
+    }
+
+    pub fn remove_expired(&mut self) {
+        let now = Instant::now();
+        self.values.retain(|_, record| record.expires > now);
+        self.size = self.values.values().map(record_size).sum();
+
+        for providers in self.providers.values_mut() {
+            providers.retain(|provider| provider.expires > now);
+        }
+        self.providers.retain(|_, providers| !providers.is_empty());
+    }
+
+    /// Records not stored or republished for interval with their remaining ttl,
+    /// they are marked as republished. Cached copies are never republished
+    pub fn due_for_republish(&mut self, interval: Duration) -> Vec<(Key, Vec<u8>, Duration)> {
+        let now = Instant::now();
+
+        self.values
+            .iter_mut()
+            .filter(|(_, record)| {
+                !record.cached && record.expires > now && now - record.stored >= interval
+            })
+            .map(|(key, record)| {
+                record.stored = now;
+                (*key, record.value.clone(), record.expires - now)
+            })
+            .collect()
+    }
+
+    /// Published values older than interval, they are marked as published again
+    pub fn due_for_publish(&mut self, interval: Duration) -> Vec<(Key, Vec<u8>)> {
+        let now = Instant::now();
+
+        self.published
+            .iter_mut()
+            .filter(|(_, published)| now - published.at >= interval)
+            .map(|(key, published)| {
+                published.at = now;
+                (*key, published.value.clone())
+            })
+            .collect()
+    }
+
+    /// Provided keys announced longer than interval ago, they are marked as announced again
+    pub fn due_for_provide(&mut self, interval: Duration) -> Vec<Key> {
+        let now = Instant::now();
+
+        self.providing
+            .iter_mut()
+            .filter(|(_, announced)| now - **announced >= interval)
+            .map(|(key, announced)| {
+                *announced = now;
+                *key
+            })
+            .collect()
+    }
+}
+
+fn record_size(record: &Record) -> usize {
+    KEY_SIZE + record.value.len()
+}
+
+#[test]
+fn value_store_test() {
+    let key = Key::new("key".to_owned());
+    let mut store = ValueStore::new(1024);
+
+    store.insert(key, b"value".to_vec(), Duration::from_millis(20));
+    assert_eq!(store.get(&key), Some(&b"value".to_vec()));
+    assert!(store.due_for_republish(Duration::from_secs(1)).is_empty());
+
+    let republished = store.due_for_republish(Duration::ZERO);
+    assert_eq!(republished.len(), 1);
+    assert!(republished[0].2 <= Duration::from_millis(20));
+
+    std::thread::sleep(Duration::from_millis(30));
+    assert_eq!(store.get(&key), None);
+    assert!(store.due_for_republish(Duration::ZERO).is_empty());
+
+    store.remove_expired();
+    assert!(store.values.is_empty());
+
+    store.cache(key, b"cached".to_vec(), Duration::from_secs(60));
+    assert_eq!(store.get(&key), Some(&b"cached".to_vec()));
+    assert!(
+        store.due_for_republish(Duration::ZERO).is_empty(),
+        "Cached copy is not republished"
+    );
+
+    store.insert(key, b"value".to_vec(), Duration::from_secs(60));
+    store.cache(key, b"cached".to_vec(), Duration::from_secs(60));
+    assert_eq!(
+        store.get(&key),
+        Some(&b"value".to_vec()),
+        "Held record is kept"
+    );
+    assert_eq!(store.due_for_republish(Duration::ZERO).len(), 1);
+    store.values.clear();
+    store.size = 0;
+
+    // replaced record makes room, expired ones are dropped when full
+    let other = Key::new("other".to_owned());
+    let ttl = Duration::from_secs(60);
+    assert!(store.insert(key, vec![0; 1024 - KEY_SIZE], ttl));
+    assert!(store.insert(key, vec![1; 1024 - KEY_SIZE], ttl));
+    assert!(!store.insert(other, vec![0], ttl), "Store is full");
+    assert!(!store.cache(other, vec![0], ttl));
+    assert!(store.insert(key, vec![0; 10], Duration::from_millis(10)));
+    std::thread::sleep(Duration::from_millis(20));
+    assert!(store.insert(other, vec![0; 1024 - KEY_SIZE], ttl));
+    assert_eq!(store.get(&key), None);
+    store.values.clear();
+    store.size = 0;
+
+    store.publish(key, b"value".to_vec());
+    assert_eq!(store.get(&key), None, "Published value is not held");
+    assert_eq!(store.due_for_publish(Duration::ZERO).len(), 1);
+    assert!(store.due_for_publish(Duration::from_secs(1)).is_empty());
+
+    store.unpublish(&key);
+    assert!(store.due_for_publish(Duration::ZERO).is_empty());
+}
+
+#[test]
+fn provider_store_test() {
+    use std::net::SocketAddr;
+
+    let key = Key::new("key".to_owned());
+    let node = |name: &str| {
+        Node::new(
+            SocketAddr::from(([127, 0, 0, 1], 1)),
+            Key::new(name.to_owned()),
+        )
+    };
+    let mut store = ValueStore::new(0);
+
+    store.add_provider(key, node("a"), Duration::from_secs(60));
+    store.add_provider(key, node("b"), Duration::from_millis(20));
+    store.add_provider(key, node("a"), Duration::from_secs(60));
+    assert_eq!(store.get_providers(&key), vec![node("b"), node("a")]);
+
+    std::thread::sleep(Duration::from_millis(30));
+    assert_eq!(store.get_providers(&key), vec![node("a")]);
+
+    store.remove_expired();
+    assert_eq!(store.providers[&key].len(), 1);
+
+    for i in 0..MAX_PROVIDERS {
+        store.add_provider(key, node(&i.to_string()), Duration::from_secs(120));
+    }
+    assert_eq!(store.get_providers(&key).len(), MAX_PROVIDERS);
+    assert!(
+        !store.get_providers(&key).contains(&node("a")),
+        "Provider closest to expiry is dropped"
+    );
+
+    store.provide(key);
+    assert!(store.due_for_provide(Duration::from_secs(1)).is_empty());
+    assert_eq!(store.due_for_provide(Duration::ZERO), vec![key]);
+
+    store.stop_providing(&key);
+    assert!(store.due_for_provide(Duration::ZERO).is_empty());
+}
@@ -1,12 +1,27 @@
 // use std::sync::mpsc;
 
-use crate::types::{distance::NodeDistance, kbucket::KBucket, key::Key, node::Node};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::{
+    puzzle::PuzzleDifficulty,
+    types::{distance::NodeDistance, kbucket::KBucket, key::Key, node::Node},
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Update {
+    Added,
+    Updated,
+    /// Bucket is full, contains least recently seen node of the bucket
+    Full(Node),
+    /// Node id doesn't solve the crypto puzzle
+    Rejected,
+}
 
 #[derive(Debug)]
 pub struct RoutingTable {
     node: Node,
     kbuckets: Vec<KBucket>,
-    k_param: usize,
+    puzzle: PuzzleDifficulty,
 }
 
 // struct RoutingTableInner {
@@ -21,57 +36,94 @@ pub struct RoutingTable {
 // }
 
 impl RoutingTable {
-    pub fn new(node: Node, n_buckets: usize, k_param: usize) -> Self {
-        let kbuckets = (0..n_buckets).map(KBucket::new).collect::<Vec<_>>();
+    pub fn new(node: Node, n_buckets: usize, k_param: usize, puzzle: PuzzleDifficulty) -> Self {
+        let kbuckets = (0..n_buckets)
+            .map(|_| KBucket::new(k_param))
+            .collect::<Vec<_>>();
 
         Self {
             node,
             kbuckets,
-            k_param,
+            puzzle,
         }
     }
 
+    /// With less buckets than key bits the last bucket holds all closer nodes
+    fn bucket_index(&self, key: &Key) -> usize {
+        crate::pure::bucket_index(&self.node.id, key).min(self.kbuckets.len() - 1)
+    }
+
     pub fn get_kbuckets(&self) -> &[KBucket] {
         &self.kbuckets
     }
 
-    pub fn update(&mut self, node: Node) {
-        let bucket_index = crate::pure::bucket_index(&self.node.id, &node.id);
-
-        if self.kbuckets[bucket_index].nodes.len() < self.k_param {
-            let node_index = self.kbuckets[bucket_index]
-                .nodes
-                .iter()
-                .position(|x| x.id == node.id);
-            match node_index {
-                Some(i) => {
-                    self.kbuckets[bucket_index].nodes.remove(i);
-                    self.kbuckets[bucket_index].nodes.push(node);
-                }
-                None => {
-                    self.kbuckets[bucket_index].nodes.push(node);
-                }
-            }
+    /// Moves node to the tail of its bucket, when the bucket is full the node is
+    /// kept in replacement cache and least recently seen node is returned so it can be pinged.
+    /// Nodes that don't solve the crypto puzzle are refused
+    pub fn update(&mut self, node: Node) -> Update {
+        if !self.puzzle.is_solved(&node) {
+            return Update::Rejected;
+        }
+
+        let bucket_index = self.bucket_index(&node.id);
+        let bucket = &mut self.kbuckets[bucket_index];
+
+        if let Some(i) = bucket.nodes.iter().position(|x| x.id == node.id) {
+            bucket.nodes.remove(i);
+            bucket.nodes.push(node);
+            bucket.last_seen.insert(node.id, SystemTime::now());
+            Update::Updated
+        } else if !bucket.is_full() {
+            bucket.replacements.retain(|x| x.id != node.id);
+            bucket.nodes.push(node);
+            bucket.last_seen.insert(node.id, SystemTime::now());
+            Update::Added
         } else {
-            // add to pending nodes or known nodes
-            // code that pings nodes need to be added
+            bucket.add_replacement(node);
+            Update::Full(bucket.nodes[0])
         }
     }
 
+    /// Removes node, its slot is taken by the freshest candidate from bucket's replacement cache
     pub fn remove(&mut self, node_id: &Key) {
-        let bucket_index = super::pure::bucket_index(&self.node.id, node_id);
+        let bucket_index = self.bucket_index(node_id);
 
-        if let Some(i) = self.kbuckets[bucket_index]
-            .nodes
-            .iter()
-            .position(|x| &x.id == node_id)
-        {
-            self.kbuckets[bucket_index].nodes.remove(i);
-        } else {
+        if self.kbuckets[bucket_index].remove(node_id).is_none() {
             error!("Removing node that is not in state")
         }
     }
 
+    /// Nodes other than this one with the time they were last seen
+    pub fn peers(&self) -> Vec<(Node, SystemTime)> {
+        self.kbuckets
+            .iter()
+            .flat_map(|bucket| {
+                bucket.nodes.iter().filter_map(|node| {
+                    let last_seen = *bucket.last_seen.get(&node.id)?;
+                    Some((*node, last_seen))
+                })
+            })
+            .filter(|(node, _)| node.id != self.node.id)
+            .collect()
+    }
+
+    /// Marks bucket in range of key as recently looked up
+    pub fn touch(&mut self, key: &Key) {
+        let bucket_index = self.bucket_index(key);
+        self.kbuckets[bucket_index].last_lookup = Instant::now();
+    }
+
+    /// Indexes of buckets with peers that had no lookup for longer than interval
+    pub fn stale_buckets(&self, interval: Duration) -> Vec<usize> {
+        self.kbuckets
+            .iter()
+            .enumerate()
+            .filter(|(_, bucket)| bucket.last_lookup.elapsed() > interval)
+            .filter(|(_, bucket)| bucket.nodes.iter().any(|node| node.id != self.node.id))
+            .map(|(index, _)| index)
+            .collect()
+    }
+
     // count only for testing will later be replaced
     pub fn get_closest_nodes(&self, key: &Key, count: usize) -> Vec<NodeDistance> {
         if count == 0 {
@@ -80,7 +132,7 @@ impl RoutingTable {
 
         let mut ret = Vec::with_capacity(count);
 
-        let mut bucket_index = crate::pure::bucket_index(&self.node.id, key);
+        let mut bucket_index = self.bucket_index(key);
         let mut bucket_index_copy = bucket_index;
 
         ret.extend(self.kbuckets[bucket_index].nodes.iter().map(|node| {
@@ -111,3 +163,80 @@ impl RoutingTable {
         ret
     }
 }
+
+#[test]
+fn update_full_bucket_test() {
+    use crate::KEY_SIZE;
+    use std::net::SocketAddr;
+
+    let node = |first_byte: u8, port: u16| {
+        let mut id = [0; KEY_SIZE];
+        id[0] = first_byte;
+        Node::new(SocketAddr::from(([127, 0, 0, 1], port)), Key(id))
+    };
+
+    let mut table = RoutingTable::new(node(0x00, 1), KEY_SIZE * 8, 2, PuzzleDifficulty::default());
+
+    // all of these fall into the same bucket
+    let (a, b, c) = (node(0x01, 2), node(0x03, 3), node(0x05, 4));
+    assert_eq!(
+        crate::pure::bucket_index(&table.node.id, &a.id),
+        crate::pure::bucket_index(&table.node.id, &c.id)
+    );
+
+    assert_eq!(table.update(a), Update::Added);
+    assert_eq!(table.update(b), Update::Added);
+    assert_eq!(table.update(c), Update::Full(a));
+
+    assert_eq!(table.update(a), Update::Updated);
+    assert_eq!(table.update(c), Update::Full(b));
+
+    // c was cached as replacement while bucket was full
+    table.remove(&b.id);
+    assert_eq!(table.get_kbuckets()[7].nodes, vec![a, c]);
+    assert_eq!(table.update(c), Update::Updated);
+
+    // without replacements slot stays free
+    table.remove(&a.id);
+    assert_eq!(table.get_kbuckets()[7].nodes, vec![c]);
+}
+
+#[test]
+fn stale_buckets_test() {
+    use crate::KEY_SIZE;
+    use std::net::SocketAddr;
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], 1));
+    let local = Node::new(addr, Key([0; KEY_SIZE]));
+
+    let mut table = RoutingTable::new(local, KEY_SIZE * 8, 2, PuzzleDifficulty::default());
+    table.update(local);
+    table.update(Node::new(addr, Key([1; KEY_SIZE])));
+
+    std::thread::sleep(Duration::from_millis(10));
+    assert_eq!(table.stale_buckets(Duration::from_millis(5)), vec![7]);
+
+    table.touch(&Key([1; KEY_SIZE]));
+    assert!(table.stale_buckets(Duration::from_millis(5)).is_empty());
+}
+
+#[test]
+fn puzzle_admission_test() {
+    use crate::KEY_SIZE;
+    use std::net::SocketAddr;
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], 1));
+    let puzzle = PuzzleDifficulty::new(0, 4);
+    let local = Node::new(addr, Key([0; KEY_SIZE]));
+    let mut table = RoutingTable::new(local, KEY_SIZE * 8, 2, puzzle);
+
+    let id = Key([1; KEY_SIZE]);
+    let unsolved = (0..=u8::MAX)
+        .map(|byte| Node::new(addr, id).with_nonce(Key([byte; KEY_SIZE])))
+        .find(|node| !puzzle.is_solved(node))
+        .unwrap();
+    assert_eq!(table.update(unsolved), Update::Rejected);
+
+    let solved = Node::new(addr, id).with_nonce(puzzle.solve_dynamic(&id).unwrap());
+    assert_eq!(table.update(solved), Update::Added);
+}
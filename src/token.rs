@@ -0,0 +1,85 @@
+//! Write tokens prove that a node asking to be stored as provider recently
+//! received GET_PROVIDERS response at its address, same as BEP 5 announce tokens
+
+use rand::{rngs::OsRng, Rng};
+use sha2::{Digest, Sha256};
+use std::{
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+/// Secret is replaced after this interval, tokens of previous secret are still accepted
+const ROTATION_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+const TOKEN_SIZE: usize = 8;
+
+#[derive(Debug)]
+pub struct WriteTokens {
+    secret: [u8; 32],
+    previous: [u8; 32],
+    rotated: Instant,
+}
+
+impl Default for WriteTokens {
+    fn default() -> Self {
+        let secret = OsRng.gen();
+
+        Self {
+            secret,
+            previous: secret,
+            rotated: Instant::now(),
+        }
+    }
+}
+
+impl WriteTokens {
+    pub fn issue(&mut self, ip: IpAddr) -> Vec<u8> {
+        self.rotate();
+        token(&self.secret, ip)
+    }
+
+    pub fn check(&mut self, ip: IpAddr, received: &[u8]) -> bool {
+        self.rotate();
+        received == token(&self.secret, ip) || received == token(&self.previous, ip)
+    }
+
+    fn rotate(&mut self) {
+        if self.rotated.elapsed() >= ROTATION_INTERVAL {
+            self.previous = self.secret;
+            self.secret = OsRng.gen();
+            self.rotated = Instant::now();
+        }
+    }
+}
+
+fn token(secret: &[u8; 32], ip: IpAddr) -> Vec<u8> {
+    let ip = match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+
+    Sha256::new()
+        .chain_update(secret)
+        .chain_update(ip)
+        .finalize()[..TOKEN_SIZE]
+        .to_vec()
+}
+
+#[test]
+fn write_tokens_test() {
+    let mut tokens = WriteTokens::default();
+    let ip = IpAddr::from([127, 0, 0, 1]);
+
+    let issued = tokens.issue(ip);
+    assert!(tokens.check(ip, &issued));
+    assert!(!tokens.check(IpAddr::from([127, 0, 0, 2]), &issued));
+
+    tokens.rotated -= ROTATION_INTERVAL;
+    assert!(
+        tokens.check(ip, &issued),
+        "Token of previous secret is accepted"
+    );
+
+    tokens.rotated -= ROTATION_INTERVAL;
+    assert!(!tokens.check(ip, &issued));
+}
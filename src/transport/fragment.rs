@@ -0,0 +1,180 @@
+//! Messages longer than the MTU are split into fragments, each sent as its own
+//! datagram and joined again by the receiver. Shorter messages are sent unchanged
+
+use crate::error::{Error, Result};
+
+use rand::{rngs::OsRng, Rng};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+/// Starts every fragment, followed by message id, fragment index and fragment count
+pub const FRAGMENT_MAGIC: [u8; 4] = *b"KDFR";
+
+const HEADER_SIZE: usize = 16;
+
+/// Longer messages are refused, both when sending and receiving
+pub const MAX_FRAGMENTS: usize = 64;
+
+/// Fragments of a message that is not complete after this long are dropped
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Messages reassembled at once, oldest one is dropped to make room for a new one
+const MAX_PARTIAL: usize = 128;
+
+/// Datagrams of at most `mtu` bytes carrying the encoded message
+pub fn fragment(encoded: Vec<u8>, mtu: usize) -> Result<Vec<Vec<u8>>> {
+    if encoded.len() <= mtu {
+        return Ok(vec![encoded]);
+    }
+
+    let chunk_size = mtu.saturating_sub(HEADER_SIZE).max(1);
+    let count = encoded.len().div_ceil(chunk_size);
+    if count > MAX_FRAGMENTS {
+        return Err(Error::MessageTooLong(encoded.len()));
+    }
+
+    let id = OsRng.gen::<u64>();
+    let datagrams = encoded
+        .chunks(chunk_size)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut datagram = Vec::with_capacity(HEADER_SIZE + chunk.len());
+            datagram.extend(FRAGMENT_MAGIC);
+            datagram.extend(id.to_be_bytes());
+            datagram.extend((index as u16).to_be_bytes());
+            datagram.extend((count as u16).to_be_bytes());
+            datagram.extend_from_slice(chunk);
+            datagram
+        })
+        .collect();
+
+    Ok(datagrams)
+}
+
+struct Partial {
+    fragments: Vec<Option<Vec<u8>>>,
+    missing: usize,
+    started: Instant,
+}
+
+/// Fragments received so far by sender and message id
+#[derive(Default)]
+pub struct Reassembler {
+    partial: HashMap<(SocketAddr, u64), Partial>,
+}
+
+impl Reassembler {
+    /// Whole message once the datagram completes it. Datagrams that are not
+    /// fragments are whole messages already, duplicated fragments are ignored
+    pub fn receive(&mut self, datagram: &[u8], from: SocketAddr) -> Result<Option<Vec<u8>>> {
+        let Some(rest) = datagram.strip_prefix(&FRAGMENT_MAGIC) else {
+            return Ok(Some(datagram.to_vec()));
+        };
+        let Some((header, chunk)) = rest.split_first_chunk::<{ HEADER_SIZE - 4 }>() else {
+            return Err(invalid("shorter than header"));
+        };
+
+        let [i0, i1, i2, i3, i4, i5, i6, i7, x0, x1, c0, c1] = *header;
+        let id = u64::from_be_bytes([i0, i1, i2, i3, i4, i5, i6, i7]);
+        let index = u16::from_be_bytes([x0, x1]) as usize;
+        let count = u16::from_be_bytes([c0, c1]) as usize;
+
+        if count > MAX_FRAGMENTS {
+            return Err(invalid("too many fragments"));
+        }
+        if index >= count {
+            return Err(invalid("index out of range"));
+        }
+
+        self.partial
+            .retain(|_, partial| partial.started.elapsed() < REASSEMBLY_TIMEOUT);
+
+        let key = (from, id);
+        if !self.partial.contains_key(&key) && self.partial.len() >= MAX_PARTIAL {
+            let oldest = self
+                .partial
+                .iter()
+                .min_by_key(|(_, partial)| partial.started)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                self.partial.remove(&oldest);
+            }
+        }
+
+        let partial = self.partial.entry(key).or_insert_with(|| Partial {
+            fragments: vec![None; count],
+            missing: count,
+            started: Instant::now(),
+        });
+
+        if partial.fragments.len() != count {
+            self.partial.remove(&key);
+            return Err(invalid("fragment count changed"));
+        }
+
+        if partial.fragments[index].is_none() {
+            partial.fragments[index] = Some(chunk.to_vec());
+            partial.missing -= 1;
+        }
+
+        if partial.missing > 0 {
+            return Ok(None);
+        }
+
+        let partial = self
+            .partial
+            .remove(&key)
+            .expect("Partial message was just updated");
+        Ok(Some(
+            partial.fragments.into_iter().flatten().flatten().collect(),
+        ))
+    }
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::Codec(format!("invalid fragment, {reason}"))
+}
+
+#[test]
+fn fragment_test() {
+    let from = SocketAddr::from(([127, 0, 0, 1], 4000));
+    let message = (0..=255).cycle().take(1000).collect::<Vec<u8>>();
+
+    let mut reassembler = Reassembler::default();
+    let short = fragment(message[..100].to_vec(), 200).unwrap();
+    assert_eq!(short, vec![message[..100].to_vec()]);
+    assert_eq!(
+        reassembler.receive(&short[0], from).unwrap(),
+        Some(message[..100].to_vec())
+    );
+
+    let mut fragments = fragment(message.clone(), 200).unwrap();
+    assert_eq!(fragments.len(), 6);
+    assert!(fragments.iter().all(|datagram| datagram.len() <= 200));
+
+    // out of order and duplicated fragments
+    fragments.swap(0, 5);
+    for datagram in &fragments[..5] {
+        assert_eq!(reassembler.receive(datagram, from).unwrap(), None);
+    }
+    assert_eq!(reassembler.receive(&fragments[4], from).unwrap(), None);
+    assert_eq!(
+        reassembler.receive(&fragments[5], from).unwrap(),
+        Some(message.clone())
+    );
+
+    // fragments of other sender are not mixed in
+    let other = SocketAddr::from(([127, 0, 0, 2], 4000));
+    let fragments = fragment(message.clone(), 200).unwrap();
+    assert_eq!(reassembler.receive(&fragments[0], other).unwrap(), None);
+    assert_eq!(reassembler.receive(&fragments[1], from).unwrap(), None);
+
+    assert!(matches!(
+        fragment(vec![0; 100_000], 200),
+        Err(Error::MessageTooLong(100_000))
+    ));
+    assert!(reassembler.receive(b"KDFR\x00", from).is_err());
+}
@@ -0,0 +1,96 @@
+use super::{tcp::TcpTransport, udp::UdpTransport, Transport};
+use crate::{
+    codec::{self, BincodeCodec},
+    config::KademliaConfig,
+    error::{Error, Result},
+    helpers::ExpectLock,
+    types::messages::RpcMessage,
+};
+
+use std::{
+    net::SocketAddr,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+/// UDP for messages that fit in one datagram such as pings and FIND_NODE,
+/// pooled TCP connections to the same port for longer ones such as STORE
+/// and FIND_VALUE carrying large values. Peers have to use it as well
+pub struct HybridTransport {
+    udp: Arc<UdpTransport>,
+    tcp: Arc<TcpTransport>,
+    mtu: usize,
+    receiver: Mutex<mpsc::Receiver<Result<(RpcMessage, SocketAddr)>>>,
+}
+
+impl HybridTransport {
+    /// Messages are in native format of network 0 and longer than the default MTU
+    /// are sent over TCP
+    pub fn bind(addr: SocketAddr, max_datagram_size: usize) -> Result<Self> {
+        let mtu = KademliaConfig::default().mtu;
+        Self::bind_with_codec(addr, max_datagram_size, mtu, BincodeCodec::default())
+    }
+
+    /// TCP listens on the port UDP socket got. Encoded messages longer than `mtu`
+    /// are sent over TCP, see [`KademliaConfigBuilder::mtu`](crate::KademliaConfigBuilder::mtu)
+    pub fn bind_with_codec(
+        addr: SocketAddr,
+        max_datagram_size: usize,
+        mtu: usize,
+        codec: BincodeCodec,
+    ) -> Result<Self> {
+        let udp = UdpTransport::bind_with_codec(addr, max_datagram_size, codec)?.mtu(mtu);
+        let udp = Arc::new(udp);
+        let tcp = Arc::new(TcpTransport::bind_with_codec(udp.local_addr()?, codec)?);
+
+        let (sender, receiver) = mpsc::channel();
+        forward(udp.clone(), sender.clone());
+        forward(tcp.clone(), sender);
+
+        Ok(Self {
+            udp,
+            tcp,
+            mtu,
+            receiver: Mutex::new(receiver),
+        })
+    }
+
+    /// Number of pooled TCP connections
+    pub fn connections(&self) -> usize {
+        self.tcp.connections()
+    }
+}
+
+/// Passes messages received by transport to the channel
+fn forward(transport: Arc<impl Transport>, sender: mpsc::Sender<Result<(RpcMessage, SocketAddr)>>) {
+    thread::spawn(move || loop {
+        let received = transport.recv();
+        let disconnected = matches!(received, Err(Error::Disconnected));
+
+        if sender.send(received).is_err() || disconnected {
+            break;
+        }
+    });
+}
+
+impl Transport for HybridTransport {
+    fn send(&self, msg: &RpcMessage, destination: SocketAddr) -> Result<()> {
+        // size of native encoding, header followed by bincode
+        if msg.size()? + codec::HEADER_SIZE > self.mtu {
+            self.tcp.send(msg, destination)
+        } else {
+            self.udp.send(msg, destination)
+        }
+    }
+
+    fn recv(&self) -> Result<(RpcMessage, SocketAddr)> {
+        self.receiver
+            .expect_lock()
+            .recv()
+            .map_err(|_| Error::Disconnected)?
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr> {
+        self.udp.local_addr()
+    }
+}
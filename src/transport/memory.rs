@@ -0,0 +1,137 @@
+use super::Transport;
+use crate::{
+    error::{Error, Result},
+    helpers::ExpectLock,
+    types::messages::RpcMessage,
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Encoded message with time it should be delivered at and its source
+type Datagram = (Instant, Vec<u8>, SocketAddr);
+
+/// In-process network that connects [`MemoryTransport`]s by address, used
+/// for running many nodes in tests without touching the OS network stack
+#[derive(Clone)]
+pub struct MemoryNetwork {
+    inner: Arc<Mutex<MemoryNetworkInner>>,
+}
+
+struct MemoryNetworkInner {
+    endpoints: HashMap<SocketAddr, mpsc::Sender<Datagram>>,
+    latency: Duration,
+    packet_loss: f64,
+    rng: StdRng,
+}
+
+impl MemoryNetwork {
+    pub fn new() -> Self {
+        Self::with_seed(0)
+    }
+
+    /// Seed decides which packets are lost
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(MemoryNetworkInner {
+                endpoints: HashMap::new(),
+                latency: Duration::ZERO,
+                packet_loss: 0.0,
+                rng: StdRng::seed_from_u64(seed),
+            })),
+        }
+    }
+
+    /// Delay before every message is delivered
+    pub fn latency(self, latency: Duration) -> Self {
+        self.inner.expect_lock().latency = latency;
+        self
+    }
+
+    /// Probability in `0.0..=1.0` that message is dropped
+    pub fn packet_loss(self, probability: f64) -> Self {
+        self.inner.expect_lock().packet_loss = probability.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn bind(&self, addr: SocketAddr) -> Result<MemoryTransport> {
+        let (sender, receiver) = mpsc::channel();
+
+        let mut inner = self.inner.expect_lock();
+        if inner.endpoints.contains_key(&addr) {
+            return Err(io::Error::from(io::ErrorKind::AddrInUse).into());
+        }
+        inner.endpoints.insert(addr, sender);
+
+        Ok(MemoryTransport {
+            network: self.clone(),
+            addr,
+            receiver: Mutex::new(receiver),
+        })
+    }
+}
+
+impl Default for MemoryNetwork {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct MemoryTransport {
+    network: MemoryNetwork,
+    addr: SocketAddr,
+    receiver: Mutex<mpsc::Receiver<Datagram>>,
+}
+
+impl Transport for MemoryTransport {
+    fn send(&self, msg: &RpcMessage, destination: SocketAddr) -> Result<()> {
+        let encoded = msg.to_bytes()?;
+        let mut inner = self.network.inner.expect_lock();
+
+        let packet_loss = inner.packet_loss;
+        if inner.rng.gen_bool(packet_loss) {
+            return Ok(());
+        }
+
+        // like UDP, messages to unknown addresses are silently lost
+        if let Some(endpoint) = inner.endpoints.get(&destination) {
+            let deliver_at = Instant::now() + inner.latency;
+            let _ = endpoint.send((deliver_at, encoded, self.addr));
+        }
+
+        Ok(())
+    }
+
+    fn recv(&self) -> Result<(RpcMessage, SocketAddr)> {
+        let (deliver_at, encoded, from) = self
+            .receiver
+            .expect_lock()
+            .recv()
+            .map_err(|_| Error::Disconnected)?;
+
+        thread::sleep(deliver_at.saturating_duration_since(Instant::now()));
+
+        Ok((RpcMessage::from_bytes(&encoded)?, from))
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.addr)
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        self.network
+            .inner
+            .expect_lock()
+            .endpoints
+            .remove(&self.addr);
+    }
+}
@@ -0,0 +1,45 @@
+//! How [`RpcMessage`]s travel between nodes
+
+pub(crate) mod fragment;
+mod hybrid;
+mod memory;
+mod tcp;
+mod udp;
+
+pub use hybrid::HybridTransport;
+pub use memory::{MemoryNetwork, MemoryTransport};
+pub use tcp::{TcpTransport, MAX_FRAME_SIZE};
+pub use udp::UdpTransport;
+
+use crate::{
+    codec::BincodeCodec, config::KademliaConfig, error::Result, types::messages::RpcMessage,
+};
+use std::{net::SocketAddr, sync::Arc};
+
+pub trait Transport: Send + Sync + 'static {
+    /// Sends message to destination, delivery is not guaranteed
+    fn send(&self, msg: &RpcMessage, destination: SocketAddr) -> Result<()>;
+
+    /// Blocks until next message arrives, returns it with address it was sent from.
+    /// Messages that can't be used return errors for which
+    /// [`Error::is_invalid_message`](crate::Error::is_invalid_message) holds and are skipped by the caller,
+    /// [`Error::Disconnected`](crate::Error::Disconnected) stops receiving
+    fn recv(&self) -> Result<(RpcMessage, SocketAddr)>;
+
+    /// Address this transport receives messages on
+    fn local_addr(&self) -> Result<SocketAddr>;
+}
+
+/// UDP transport, or hybrid one if TCP is enabled, bound to the address of config
+pub(crate) fn bind(config: &KademliaConfig) -> Result<Arc<dyn Transport>> {
+    let (addr, size) = (config.addresses.bind, config.max_datagram_size);
+    let codec = BincodeCodec::new(config.network_id);
+
+    if config.tcp {
+        let transport = HybridTransport::bind_with_codec(addr, size, config.mtu, codec)?;
+        Ok(Arc::new(transport))
+    } else {
+        let transport = UdpTransport::bind_with_codec(addr, size, codec)?.mtu(config.mtu);
+        Ok(Arc::new(transport))
+    }
+}
@@ -0,0 +1,350 @@
+use super::Transport;
+use crate::{
+    codec::{BincodeCodec, Codec},
+    error::{Error, Result},
+    helpers::ExpectLock,
+    types::messages::RpcMessage,
+};
+
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Longer frames are not sent, and close the connection they arrive on
+pub const MAX_FRAME_SIZE: usize = 1 << 20;
+
+/// Most pooled connections, least recently used one is closed to make room
+const MAX_CONNECTIONS: usize = 64;
+
+/// Most accepted connections that are not pooled, further ones are closed at once
+const MAX_UNPOOLED: usize = 64;
+
+/// Pooled connections unused and connections without messages for this long are closed
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+type Received = Result<(RpcMessage, SocketAddr)>;
+
+struct Connection {
+    id: u64,
+    writer: Arc<Mutex<TcpStream>>,
+    /// Clone of the stream for closing it while a write is in progress
+    control: TcpStream,
+    used: Instant,
+}
+
+impl Connection {
+    fn close(&self) {
+        let _ = self.control.shutdown(Shutdown::Both);
+    }
+}
+
+/// State shared with the threads reading connections
+#[derive(Clone)]
+struct Shared {
+    pool: Arc<Mutex<HashMap<SocketAddr, Connection>>>,
+    codec: Arc<dyn Codec>,
+    sender: mpsc::Sender<Received>,
+    next_id: Arc<AtomicU64>,
+    /// Accepted connections that are not pooled
+    unpooled: Arc<AtomicUsize>,
+}
+
+/// Messages in length prefixed frames over TCP, one pooled connection per peer
+/// is used in both directions. Connecting side starts with a frame holding the
+/// port it listens on, so messages are received from the address peer is reachable at.
+/// Anyone can claim a port, accepted connection is pooled only once a message signed
+/// by the node at the claimed address arrives on it, and never replaces a pooled one
+pub struct TcpTransport {
+    local_addr: SocketAddr,
+    shared: Shared,
+    receiver: Mutex<mpsc::Receiver<Received>>,
+}
+
+impl TcpTransport {
+    /// Messages are in native format of network 0
+    pub fn bind(addr: SocketAddr) -> Result<Self> {
+        Self::bind_with_codec(addr, BincodeCodec::default())
+    }
+
+    /// Frames hold messages in wire format of codec instead of bincode
+    pub fn bind_with_codec(addr: SocketAddr, codec: impl Codec) -> Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let (sender, receiver) = mpsc::channel();
+
+        let shared = Shared {
+            pool: Arc::new(Mutex::new(HashMap::new())),
+            codec: Arc::new(codec),
+            sender,
+            next_id: Arc::new(AtomicU64::new(0)),
+            unpooled: Arc::new(AtomicUsize::new(0)),
+        };
+
+        let acceptor = shared.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if acceptor.unpooled.fetch_add(1, Ordering::Relaxed) >= MAX_UNPOOLED {
+                            acceptor.unpooled.fetch_sub(1, Ordering::Relaxed);
+                            warn!(
+                                "Refusing connection, {} accepted are not pooled",
+                                MAX_UNPOOLED
+                            );
+                            continue;
+                        }
+
+                        let shared = acceptor.clone();
+                        thread::spawn(move || {
+                            if let Err(err) = shared.clone().accept(stream) {
+                                shared.unpooled.fetch_sub(1, Ordering::Relaxed);
+                                warn!("Error accepting connection: {}", err);
+                            }
+                        });
+                    }
+                    Err(err) => error!("Error accepting connection: {}", err),
+                }
+            }
+        });
+
+        Ok(Self {
+            local_addr,
+            shared,
+            receiver: Mutex::new(receiver),
+        })
+    }
+
+    /// Number of pooled connections
+    pub fn connections(&self) -> usize {
+        self.shared.pool.expect_lock().len()
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send(&self, msg: &RpcMessage, destination: SocketAddr) -> Result<()> {
+        let frame = self.shared.codec.encode(msg)?;
+        if frame.len() > MAX_FRAME_SIZE {
+            return Err(Error::MessageTooLong(frame.len()));
+        }
+
+        let writer = match self.shared.pooled(destination) {
+            Some(writer) => writer,
+            None => self.shared.connect(destination, self.local_addr.port())?,
+        };
+
+        let written = write_frame(&mut writer.expect_lock(), &frame);
+        if written.is_err() {
+            self.shared.remove(destination, None);
+        }
+        written
+    }
+
+    fn recv(&self) -> Result<(RpcMessage, SocketAddr)> {
+        self.receiver
+            .expect_lock()
+            .recv()
+            .map_err(|_| Error::Disconnected)?
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+}
+
+impl Shared {
+    /// Reads port peer listens on, then serves the connection unpooled
+    fn accept(self, mut stream: TcpStream) -> Result<()> {
+        stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+        let hello = read_frame(&mut stream, 2)?;
+
+        let port = <[u8; 2]>::try_from(hello.as_slice())
+            .map_err(|_| Error::Codec("invalid hello frame".to_owned()))?;
+
+        let mut peer = stream.peer_addr()?;
+        peer.set_port(u16::from_be_bytes(port));
+
+        self.serve(stream, peer, None);
+        Ok(())
+    }
+
+    /// Opens and pools connection to destination, returns its writer
+    fn connect(&self, destination: SocketAddr, port: u16) -> Result<Arc<Mutex<TcpStream>>> {
+        let mut stream = TcpStream::connect_timeout(&destination, CONNECT_TIMEOUT)?;
+        write_frame(&mut stream, &port.to_be_bytes())?;
+
+        let reader = stream.try_clone()?;
+        let (id, writer) = self.insert(destination, stream)?;
+
+        let shared = self.clone();
+        thread::spawn(move || shared.serve(reader, destination, Some(id)));
+        Ok(writer)
+    }
+
+    /// Forwards received messages until connection closes or idles, then removes it
+    /// from pool. Accepted connection without id is pooled once the node at peer
+    /// address signs a message on it
+    fn serve(self, mut stream: TcpStream, peer: SocketAddr, mut id: Option<u64>) {
+        if let Err(err) = stream.set_read_timeout(Some(IDLE_TIMEOUT)) {
+            warn!("Error serving connection to {}: {}", peer, err);
+        }
+
+        loop {
+            let frame = match read_frame(&mut stream, MAX_FRAME_SIZE) {
+                Ok(frame) => frame,
+                Err(err) => {
+                    debug!("Closing connection to {}: {}", peer, err);
+                    break;
+                }
+            };
+
+            let received = self.codec.decode(&frame, peer);
+            match &received {
+                Ok(msg) if id.is_none() && is_signed_by(msg, peer) => {
+                    id = self.adopt(peer, &stream);
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    let rejection = self.codec.reject(&frame, err);
+                    if let Some((rejection, writer)) = rejection.zip(self.pooled(peer)) {
+                        let _ = write_frame(&mut writer.expect_lock(), &rejection);
+                    }
+                }
+            }
+            if self.sender.send(received.map(|msg| (msg, peer))).is_err() {
+                break;
+            }
+        }
+
+        let _ = stream.shutdown(Shutdown::Both);
+        if id.is_some() {
+            self.remove(peer, id);
+        } else {
+            self.unpooled.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Pools accepted connection unless peer has one already, connection opened
+    /// by us stays pooled if both sides connected at once. Returns id if pooled
+    fn adopt(&self, peer: SocketAddr, stream: &TcpStream) -> Option<u64> {
+        if self.pool.expect_lock().contains_key(&peer) {
+            return None;
+        }
+
+        let inserted = stream
+            .try_clone()
+            .map_err(Error::from)
+            .and_then(|stream| self.insert(peer, stream));
+        match inserted {
+            Ok((id, _)) => {
+                self.unpooled.fetch_sub(1, Ordering::Relaxed);
+                Some(id)
+            }
+            Err(err) => {
+                warn!("Error pooling connection to {}: {}", peer, err);
+                None
+            }
+        }
+    }
+
+    fn pooled(&self, peer: SocketAddr) -> Option<Arc<Mutex<TcpStream>>> {
+        let mut pool = self.pool.expect_lock();
+        let connection = pool.get_mut(&peer)?;
+        connection.used = Instant::now();
+        Some(connection.writer.clone())
+    }
+
+    fn insert(&self, peer: SocketAddr, stream: TcpStream) -> Result<(u64, Arc<Mutex<TcpStream>>)> {
+        stream.set_nodelay(true)?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let connection = Connection {
+            id,
+            control: stream.try_clone()?,
+            writer: Arc::new(Mutex::new(stream)),
+            used: Instant::now(),
+        };
+        let writer = connection.writer.clone();
+
+        let mut pool = self.pool.expect_lock();
+        pool.retain(|_, connection| {
+            let idle = connection.used.elapsed() >= IDLE_TIMEOUT;
+            if idle {
+                connection.close();
+            }
+            !idle
+        });
+
+        if pool.len() >= MAX_CONNECTIONS {
+            let oldest = pool
+                .iter()
+                .min_by_key(|(_, connection)| connection.used)
+                .map(|(peer, _)| *peer);
+            if let Some(oldest) = oldest.and_then(|oldest| pool.remove(&oldest)) {
+                oldest.close();
+            }
+        }
+
+        if let Some(replaced) = pool.insert(peer, connection) {
+            replaced.close();
+        }
+        Ok((id, writer))
+    }
+
+    /// Closes pooled connection to peer, only if it has given id when one is given
+    fn remove(&self, peer: SocketAddr, id: Option<u64>) {
+        let mut pool = self.pool.expect_lock();
+        if pool
+            .get(&peer)
+            .is_some_and(|connection| id.is_none_or(|id| connection.id == id))
+        {
+            if let Some(connection) = pool.remove(&peer) {
+                connection.close();
+            }
+        }
+    }
+}
+
+/// Signature binds source node id to its key, and the node claims to be at peer
+/// address. Nodes bound to all interfaces advertise unspecified ip
+fn is_signed_by(msg: &RpcMessage, peer: SocketAddr) -> bool {
+    let addr = msg.source.addr;
+    let addr_matches = addr == peer || (addr.ip().is_unspecified() && addr.port() == peer.port());
+
+    addr_matches && msg.verify().is_ok()
+}
+
+/// Frame is payload length as big endian u32 followed by the payload
+fn write_frame(stream: &mut TcpStream, payload: &[u8]) -> Result<()> {
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend((payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)?;
+    Ok(())
+}
+
+fn read_frame(stream: &mut TcpStream, max_size: usize) -> Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max_size {
+        return Err(Error::MessageTooLong(len));
+    }
+
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload)?;
+    Ok(payload)
+}
@@ -0,0 +1,87 @@
+use super::{
+    fragment::{self, Reassembler},
+    Transport,
+};
+use crate::{
+    codec::{BincodeCodec, Codec},
+    config::KademliaConfig,
+    error::Result,
+    helpers::ExpectLock,
+    types::messages::RpcMessage,
+};
+
+use std::{
+    net::{SocketAddr, UdpSocket},
+    sync::Mutex,
+};
+
+/// Default transport, one message per datagram unless it is longer than the MTU
+pub struct UdpTransport {
+    socket: UdpSocket,
+    buf: Mutex<Vec<u8>>,
+    codec: Box<dyn Codec>,
+    mtu: usize,
+    reassembler: Mutex<Reassembler>,
+}
+
+impl UdpTransport {
+    /// Longer datagrams than `max_datagram_size` are truncated and dropped as malformed.
+    /// Messages are in native format of network 0
+    pub fn bind(addr: SocketAddr, max_datagram_size: usize) -> Result<Self> {
+        Self::bind_with_codec(addr, max_datagram_size, BincodeCodec::default())
+    }
+
+    /// Datagrams are encoded in wire format of codec instead of bincode
+    pub fn bind_with_codec(
+        addr: SocketAddr,
+        max_datagram_size: usize,
+        codec: impl Codec,
+    ) -> Result<Self> {
+        Ok(Self {
+            socket: UdpSocket::bind(addr)?,
+            buf: Mutex::new(vec![0u8; max_datagram_size]),
+            codec: Box::new(codec),
+            mtu: KademliaConfig::default().mtu,
+            reassembler: Mutex::new(Reassembler::default()),
+        })
+    }
+
+    /// Encoded messages longer than `mtu` are sent in fragments, see [`KademliaConfigBuilder::mtu`](crate::KademliaConfigBuilder::mtu)
+    pub fn mtu(mut self, mtu: usize) -> Self {
+        self.mtu = mtu;
+        self
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send(&self, msg: &RpcMessage, destination: SocketAddr) -> Result<()> {
+        let encoded = self.codec.encode(msg)?;
+        for datagram in fragment::fragment(encoded, self.mtu)? {
+            self.socket.send_to(&datagram, destination)?;
+        }
+        Ok(())
+    }
+
+    fn recv(&self) -> Result<(RpcMessage, SocketAddr)> {
+        let mut buf = self.buf.expect_lock();
+
+        loop {
+            let (len, from) = self.socket.recv_from(&mut buf)?;
+            let received = self.reassembler.expect_lock().receive(&buf[..len], from)?;
+
+            if let Some(encoded) = received {
+                let decoded = self.codec.decode(&encoded, from);
+                if let Err(err) = &decoded {
+                    if let Some(rejection) = self.codec.reject(&encoded, err) {
+                        self.socket.send_to(&rejection, from)?;
+                    }
+                }
+                return Ok((decoded?, from));
+            }
+        }
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+}
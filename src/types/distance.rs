@@ -33,7 +33,7 @@ impl PartialEq for NodeDistance {
     fn eq(&self, other: &NodeDistance) -> bool {
         let mut equal = true;
         let mut i = 0;
-        while equal && i < 32 {
+        while equal && i < KEY_SIZE {
             if self.distance.0[i] != other.distance.0[i] {
                 equal = false;
             }
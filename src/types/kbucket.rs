@@ -1,16 +1,65 @@
-use super::node::Node;
+use std::{
+    collections::HashMap,
+    time::{Instant, SystemTime},
+};
+
+use super::{key::Key, node::Node};
 
 #[derive(Debug)]
 pub struct KBucket {
     pub nodes: Vec<Node>, // This should be handled better
+    /// Candidates seen while bucket was full, freshest is last
+    pub replacements: Vec<Node>,
     pub size: usize,
+    /// Last time lookup was made for a key in range of this bucket
+    pub last_lookup: Instant,
+    /// When nodes and replacements were last seen, wall clock so it can be persisted
+    pub last_seen: HashMap<Key, SystemTime>,
 }
 
 impl KBucket {
     pub fn new(size: usize) -> Self {
         Self {
             nodes: vec![],
+            replacements: vec![],
             size,
+            last_lookup: Instant::now(),
+            last_seen: HashMap::new(),
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.nodes.len() >= self.size
+    }
+
+    /// Records candidate for the next free slot, stalest candidate is dropped when cache is full
+    pub fn add_replacement(&mut self, node: Node) {
+        self.replacements.retain(|x| x.id != node.id);
+
+        if self.replacements.len() >= self.size {
+            let stalest = self.replacements.remove(0);
+            self.last_seen.remove(&stalest.id);
         }
+
+        self.last_seen.insert(node.id, SystemTime::now());
+        self.replacements.push(node);
+    }
+
+    /// Removes node from the bucket and promotes the freshest replacement in its place
+    pub fn remove(&mut self, node_id: &Key) -> Option<Node> {
+        if let Some(i) = self.replacements.iter().position(|x| &x.id == node_id) {
+            self.last_seen.remove(node_id);
+            return Some(self.replacements.remove(i));
+        }
+
+        let i = self.nodes.iter().position(|x| &x.id == node_id)?;
+        let removed = self.nodes.remove(i);
+        self.last_seen.remove(node_id);
+
+        if let Some(replacement) = self.replacements.pop() {
+            self.nodes.push(replacement);
+        }
+
+        Some(removed)
     }
 }
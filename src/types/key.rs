@@ -1,17 +1,71 @@
-use crate::KEY_SIZE;
+use crate::{error, identity, KEY_SIZE};
+use ed25519_dalek::VerifyingKey;
 use sha2::{Digest, Sha256};
-use std::fmt::{Debug, Display, Error, Formatter};
+use std::{
+    fmt::{Debug, Display, Error, Formatter},
+    str::FromStr,
+};
 
 use super::distance::Distance;
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Copy)]
 pub struct Key(pub(crate) [u8; KEY_SIZE]);
 
+/// Length of keys and how node ids are derived from public keys, every node of
+/// a network has to use the same one. Shorter keys leave trailing bytes of
+/// [`Key`] zero, and keys given to a node are cut to its key size
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeySpace {
+    /// 256-bit keys, node id is the sha2-256 of the Ed25519 public key
+    #[default]
+    Native,
+    /// 160-bit keys of the BitTorrent Mainline DHT, node id is the leading
+    /// 160 bits of the native one
+    Mainline,
+    /// 256-bit keys of libp2p Kademlia, node id is the sha2-256 of the peer id so
+    /// it matches the key libp2p peers give the node
+    Libp2p,
+}
+
+impl KeySpace {
+    pub(crate) const ALL: [KeySpace; 3] = [KeySpace::Native, KeySpace::Mainline, KeySpace::Libp2p];
+
+    /// Length of keys in bytes
+    pub fn key_size(self) -> usize {
+        match self {
+            KeySpace::Native | KeySpace::Libp2p => KEY_SIZE,
+            KeySpace::Mainline => 20,
+        }
+    }
+
+    pub fn node_id(self, public_key: &VerifyingKey) -> Key {
+        match self {
+            KeySpace::Libp2p => Key::from_hash(&identity::peer_id(public_key)),
+            _ => self.truncate(&Key::from_public_key(public_key)),
+        }
+    }
+
+    /// Leading key size bytes of key, the rest is zeroed
+    pub fn truncate(self, key: &Key) -> Key {
+        let mut truncated = *key;
+        truncated.0[self.key_size()..].fill(0);
+        truncated
+    }
+}
+
 impl Key {
     pub fn new(input: String) -> Self {
-        let result = Sha256::digest(input.as_bytes());
-        let hash = result.into();
-        Self(hash)
+        Self::from_hash(input.as_bytes())
+    }
+
+    /// Node id bound to a public key in the native key space
+    pub fn from_public_key(public_key: &VerifyingKey) -> Self {
+        Self::from_hash(public_key.as_bytes())
+    }
+
+    /// SHA-256 of input
+    pub(crate) fn from_hash(input: &[u8]) -> Self {
+        Self(Sha256::digest(input).into())
     }
 
     pub fn distance(&self, key: &Key) -> Distance {
@@ -21,7 +75,7 @@ impl Key {
 
 impl Display for Key {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
-        self.0.iter().map(|x| write!(f, "{x:X}")).try_collect()?;
+        self.0.iter().map(|x| write!(f, "{x:02X}")).try_collect::<()>()?;
         Ok(())
     }
 }
@@ -31,3 +85,28 @@ impl Debug for Key {
         Display::fmt(&self, f)
     }
 }
+
+impl FromStr for Key {
+    type Err = error::Error;
+
+    /// Parses hex representation produced by [`Display`], or 40 digits of a
+    /// 160-bit key such as an info hash
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let size = KeySpace::ALL
+            .into_iter()
+            .map(KeySpace::key_size)
+            .find(|size| s.len() == size * 2)
+            .ok_or(error::Error::InvalidKey)?;
+        if !s.is_ascii() {
+            return Err(error::Error::InvalidKey);
+        }
+
+        let mut key = [0; KEY_SIZE];
+        for (i, byte) in key.iter_mut().enumerate().take(size) {
+            *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16)
+                .map_err(|_| error::Error::InvalidKey)?;
+        }
+
+        Ok(Self(key))
+    }
+}
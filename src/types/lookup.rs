@@ -0,0 +1,53 @@
+use super::{
+    distance::{Distance, NodeDistance},
+    node::Node,
+};
+
+/// Node found by [`Kademlia::lookup`](crate::Kademlia::lookup), `path` is index
+/// of the disjoint path that found it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LookupEntry {
+    pub node: Node,
+    pub distance: Distance,
+    pub path: usize,
+}
+
+impl LookupEntry {
+    pub fn new(entry: NodeDistance, path: usize) -> Self {
+        Self {
+            node: entry.node,
+            distance: entry.distance,
+            path,
+        }
+    }
+}
+
+impl From<LookupEntry> for NodeDistance {
+    fn from(entry: LookupEntry) -> Self {
+        NodeDistance::new(entry.node, entry.distance)
+    }
+}
+
+/// Work done by a lookup, with disjoint paths RPCs and failures of all paths
+/// are summed and rounds is the highest number of rounds of a path
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LookupStats {
+    pub rounds: usize,
+    pub rpcs_sent: usize,
+    pub failures: usize,
+}
+
+impl LookupStats {
+    pub(crate) fn merge(&mut self, path: LookupStats) {
+        self.rounds = self.rounds.max(path.rounds);
+        self.rpcs_sent += path.rpcs_sent;
+        self.failures += path.failures;
+    }
+}
+
+/// Result of [`Kademlia::lookup`](crate::Kademlia::lookup), k closest nodes that responded
+#[derive(Clone, Debug, Default)]
+pub struct LookupResult {
+    pub nodes: Vec<LookupEntry>,
+    pub stats: LookupStats,
+}
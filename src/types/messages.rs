@@ -1,17 +1,61 @@
-use super::{distance::NodeDistance, key::Key};
+use super::{
+    key::{Key, KeySpace},
+    node::Node,
+};
+use crate::{
+    error::{Error, Result},
+    identity::Identity,
+};
+
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use std::{net::SocketAddr, time::Duration};
 
 #[derive(Serialize, Deserialize)]
 /// this should have same enum variants as [`Response`] with different values
 pub enum Request {
-    Ping,          // PING
-    FindNode(Key), // FIND_NODE
+    Ping,                                // PING
+    FindNode(Key),                       // FIND_NODE
+    Store(Key, Vec<u8>, Duration, bool), // STORE with time to live, true for cached copy
+    FindValue(Key),                      // FIND_VALUE
+    AddProvider(Key, WriteToken),        // ADD_PROVIDER, source of the request is the provider
+    GetProviders(Key),                   // GET_PROVIDERS
+}
+
+#[derive(Serialize, Deserialize)]
+/// Proof for the receiver of ADD_PROVIDER that the provider is reachable at its address
+pub enum WriteToken {
+    /// Token the receiver returned to that address with GET_PROVIDERS response
+    Issued(Vec<u8>),
+    /// Request arrived on a connection whose handshake verified the peer, as libp2p
+    /// streams do. Never serialized, so peers can't claim it in messages
+    #[serde(skip)]
+    Transport,
 }
 
 #[derive(Serialize, Deserialize)]
-/// to prevent possible errors this should have GET and PUT variants too
 pub enum Response {
     Pong,
-    FindNode(Vec<NodeDistance>),
+    /// Closest nodes, closest first. Receiver computes distances itself
+    FindNode(Vec<Node>),
+    Store,
+    /// Requested key with its value or closest nodes
+    FindValue(Key, FindValueResult),
+    AddProvider,
+    /// Providers and closest nodes to the key, with write token the requester needs
+    /// for ADD_PROVIDER
+    GetProviders(Vec<Node>, Vec<Node>, Vec<u8>),
+    /// Request was refused, with reason
+    Rejected(String),
+    /// Request was of protocol version or network peer doesn't take part in,
+    /// with version and network id of the peer
+    Incompatible(u16, u32),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// FIND_VALUE returns the value if the node holds it, otherwise behaves like FIND_NODE
+pub enum FindValueResult {
+    Value(Vec<u8>),
+    Nodes(Vec<Node>),
 }
 
 #[derive(Serialize, Deserialize)]
@@ -21,24 +65,124 @@ pub enum Message {
 }
 
 #[derive(Serialize, Deserialize)]
+/// Message signed by the sender, `source.id` has to be derived from `public_key`.
+/// Public key and signature are missing in wire formats without signatures
 pub struct RpcMessage {
-    pub token: usize,
-    pub source: u16, // Source should be node
+    pub token: u128,
+    pub source: Node,
     pub message: Message,
+    pub public_key: Option<VerifyingKey>,
+    pub signature: Option<Signature>,
 }
 
 pub struct RpcRequest {
-    pub token: usize,
-    pub source: u16, // Source should be node
+    pub token: u128,
+    pub source: Node,
+    /// Address request actually came from, source address is only claimed by the sender
+    pub from: SocketAddr,
     pub payload: Request,
 }
 
 impl RpcMessage {
-    pub(crate) fn to_bytes(&self) -> Vec<u8> {
-        bincode::serialize(self).expect("Error serializing")
+    /// Signs token, source and message with the identity of the source
+    pub fn signed(
+        token: u128,
+        source: Node,
+        message: Message,
+        identity: &Identity,
+    ) -> Result<Self> {
+        let signature = identity.sign(&signed_bytes(token, &source, &message)?);
+
+        Ok(Self {
+            token,
+            source,
+            message,
+            public_key: Some(identity.public_key()),
+            signature: Some(signature),
+        })
+    }
+
+    /// Signed message that encodes to at most `max_size` bytes, farthest nodes of
+    /// FIND_NODE and FIND_VALUE responses are dropped until it does. Other
+    /// messages are returned whole however long they are
+    pub fn signed_within(
+        token: u128,
+        source: Node,
+        message: Message,
+        identity: &Identity,
+        max_size: usize,
+    ) -> Result<Self> {
+        let mut msg = Self::signed(token, source, message, identity)?;
+        let excess = msg.size()?.saturating_sub(max_size);
+        if excess == 0 {
+            return Ok(msg);
+        }
+
+        let nodes = match &mut msg.message {
+            Message::Response(Response::FindNode(nodes)) => nodes,
+            Message::Response(Response::FindValue(_, FindValueResult::Nodes(nodes))) => nodes,
+            Message::Response(Response::GetProviders(_, nodes, _)) => nodes,
+            _ => return Ok(msg),
+        };
+
+        let mut dropped = 0;
+        while dropped < excess {
+            let Some(node) = nodes.pop() else {
+                break;
+            };
+            dropped += bincode::serialized_size(&node)? as usize;
+        }
+
+        Self::signed(token, source, msg.message, identity)
+    }
+
+    /// Message without signature, for wire formats that can't carry one
+    pub fn unsigned(token: u128, source: Node, message: Message) -> Self {
+        Self {
+            token,
+            source,
+            message,
+            public_key: None,
+            signature: None,
+        }
+    }
+
+    pub fn is_signed(&self) -> bool {
+        self.public_key.is_some() || self.signature.is_some()
+    }
+
+    /// Checks the signature and that source node id belongs to the public key
+    /// in some key space, see [`KeySpace::node_id`]
+    pub fn verify(&self) -> Result<()> {
+        let (Some(public_key), Some(signature)) = (self.public_key, self.signature) else {
+            return Err(Error::InvalidSignature);
+        };
+
+        let ids = KeySpace::ALL.map(|key_space| key_space.node_id(&public_key));
+        if !ids.contains(&self.source.id) {
+            return Err(Error::InvalidSignature);
+        }
+
+        let signed = signed_bytes(self.token, &self.source, &self.message)?;
+        public_key
+            .verify(&signed, &signature)
+            .map_err(|_| Error::InvalidSignature)
     }
 
-    pub(crate) fn from_bytes(bytes: &[u8]) -> Self {
-        bincode::deserialize(bytes).expect("Error serializing")
+    /// Length of [`to_bytes`](Self::to_bytes) without encoding the message
+    pub fn size(&self) -> Result<usize> {
+        Ok(bincode::serialized_size(self)? as usize)
     }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+fn signed_bytes(token: u128, source: &Node, message: &Message) -> Result<Vec<u8>> {
+    Ok(bincode::serialize(&(token, source, message))?)
 }
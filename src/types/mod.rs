@@ -1,5 +1,6 @@
 pub mod distance;
 pub mod kbucket;
 pub mod key;
+pub mod lookup;
 pub mod messages;
 pub mod node;
@@ -1,18 +1,27 @@
+use std::net::SocketAddr;
+
 use super::key::Key;
+use crate::KEY_SIZE;
 
 #[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Hash, Copy, Debug)]
-/// In white paper this should also contain address
 pub struct Node {
-    pub port: u16,
+    pub addr: SocketAddr,
     pub id: Key,
+    /// Solution of S/Kademlia dynamic puzzle for id, see [`PuzzleDifficulty`](crate::PuzzleDifficulty)
+    pub nonce: Key,
 }
 
 impl Node {
-    pub fn new(port: u16, id: Key) -> Self {
-        Node { port, id }
+    pub fn new(addr: SocketAddr, id: Key) -> Self {
+        Node {
+            addr,
+            id,
+            nonce: Key([0; KEY_SIZE]),
+        }
     }
 
-    pub fn get_addr(&self) -> String {
-        format!("{}:{}", env!("IP_ADDR"), self.port)
+    pub fn with_nonce(mut self, nonce: Key) -> Self {
+        self.nonce = nonce;
+        self
     }
 }
@@ -0,0 +1,110 @@
+#![cfg(feature = "async")]
+
+mod common;
+
+use common::{async_local_network, local};
+use kademlia::{
+    asynchronous::Kademlia, transport::MemoryNetwork, Identity, KademliaConfig, Key, Node,
+};
+use std::{env, fs, time::Duration};
+
+const NODE_COUNT: usize = 16;
+const BASE_PORT: usize = 13000;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_node_finding() {
+    let config = |port| KademliaConfig::builder().bind_addr(local(port)).build();
+    let nodes = async_local_network(BASE_PORT, NODE_COUNT, config).await;
+    let (seed_node, nodes) = nodes.split_first().unwrap();
+
+    assert_eq!(seed_node.get_all_know_nodes().len(), NODE_COUNT + 1);
+
+    let target = nodes[NODE_COUNT - 1].node().id;
+    let found = nodes[0].lookup_nodes(&target).await.unwrap();
+    assert_eq!(
+        found[0].node.id, target,
+        "Closest node should be target itself"
+    );
+
+    let missing = Node::new(local(BASE_PORT - 1), Key::new("missing".to_owned()));
+    assert!(nodes[0].ping(missing).await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_publisher_refreshes_values() {
+    let config = |port: usize| {
+        KademliaConfig::builder()
+            .bind_addr(local(port))
+            .record_ttl(Duration::from_millis(500))
+            .publish_interval(Duration::from_millis(200))
+            .build()
+    };
+
+    let nodes = async_local_network(BASE_PORT + 100, 3, config).await;
+    let seed_node = nodes[0].clone();
+
+    let key = Key::new("refreshed-key".to_owned());
+    assert!(nodes[1].put(key, b"value".to_vec()).await.unwrap() > 0);
+
+    // blocking node reads the value after the first copies expired
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let reader = tokio::task::spawn_blocking(move || {
+        let mut reader =
+            kademlia::Kademlia::with_config(config(BASE_PORT + 104), Identity::generate()).unwrap();
+        reader.bootstrap(*seed_node.node()).unwrap();
+        reader.get(&key).unwrap()
+    });
+    assert_eq!(reader.await.unwrap(), Some(b"value".to_vec()));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_memory_network_values_and_providers() {
+    let network = MemoryNetwork::new();
+    let config = KademliaConfig::builder()
+        .request_timeout(Duration::from_millis(200))
+        .build();
+
+    let mut nodes: Vec<Kademlia> = vec![];
+    for index in 0..8 {
+        let transport = network.bind(local(BASE_PORT + 200 + index)).unwrap();
+        let kademlia = Kademlia::with_transport(config, Identity::generate(), transport)
+            .await
+            .unwrap();
+        if let Some(seed_node) = nodes.first() {
+            kademlia.bootstrap(*seed_node.node()).await.unwrap();
+        }
+        nodes.push(kademlia);
+    }
+
+    let key = Key::new("async-value".to_owned());
+    assert!(nodes[1].put(key, b"value".to_vec()).await.unwrap() > 0);
+    assert_eq!(nodes[7].get(&key).await.unwrap(), Some(b"value".to_vec()));
+
+    let missing = Key::new("async-missing".to_owned());
+    assert_eq!(nodes[7].get(&missing).await.unwrap(), None);
+
+    let provided = Key::new("async-provided".to_owned());
+    assert!(nodes[2].start_providing(provided).await.unwrap() > 0);
+    let providers = nodes[6].get_providers(&provided).await.unwrap();
+    assert_eq!(providers.len(), 1);
+    assert_eq!(providers[0].id, nodes[2].node().id);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_save_and_load_state() {
+    let path = env::temp_dir().join(format!("kademlia-async-state-{}.state", std::process::id()));
+    let config = |port| KademliaConfig::builder().bind_addr(local(port)).build();
+
+    let nodes = async_local_network(BASE_PORT + 300, 2, config).await;
+    nodes[1].save_state(&path).await.unwrap();
+
+    let restored = Kademlia::load_state(&path, config(BASE_PORT + 303))
+        .await
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(restored.node().id, nodes[1].node().id);
+
+    let known = restored.get_all_know_nodes();
+    assert!(known.iter().any(|known| known.id == nodes[0].node().id));
+}
@@ -0,0 +1,60 @@
+//! Helpers shared by integration tests, every test binary uses only some of them
+#![allow(dead_code)]
+
+use kademlia::{Identity, Kademlia, KademliaConfig};
+use std::net::SocketAddr;
+
+pub fn local(port: usize) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port as u16))
+}
+
+/// Seed node and `count` nodes bootstrapped from it, seed first.
+/// `node` creates the node of every index
+pub fn network(count: usize, node: impl Fn(usize) -> Kademlia) -> Vec<Kademlia> {
+    let seed_node = node(0);
+
+    let mut nodes = vec![seed_node.clone()];
+    for index in 1..=count {
+        let mut kademlia = node(index);
+        kademlia.bootstrap(*seed_node.node()).unwrap();
+        nodes.push(kademlia);
+    }
+    nodes
+}
+
+/// [`network`] of nodes on consecutive local ports from `base_port`, `config`
+/// gets the port of the node
+pub fn local_network(
+    base_port: usize,
+    count: usize,
+    config: impl Fn(usize) -> KademliaConfig,
+) -> Vec<Kademlia> {
+    network(count, |index| {
+        let config = config(base_port + index);
+        Kademlia::with_config(config, Identity::generate()).unwrap()
+    })
+}
+
+/// [`local_network`] of async nodes
+#[cfg(feature = "async")]
+pub async fn async_local_network(
+    base_port: usize,
+    count: usize,
+    config: impl Fn(usize) -> KademliaConfig,
+) -> Vec<kademlia::asynchronous::Kademlia> {
+    use kademlia::asynchronous::Kademlia;
+
+    let seed_node = Kademlia::with_config(config(base_port), Identity::generate())
+        .await
+        .unwrap();
+
+    let mut nodes = vec![seed_node.clone()];
+    for port in base_port + 1..=base_port + count {
+        let kademlia = Kademlia::with_config(config(port), Identity::generate())
+            .await
+            .unwrap();
+        kademlia.bootstrap(*seed_node.node()).await.unwrap();
+        nodes.push(kademlia);
+    }
+    nodes
+}
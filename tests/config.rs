@@ -0,0 +1,177 @@
+mod common;
+
+use common::{local, local_network};
+use kademlia::{
+    codec::{BincodeCodec, Codec},
+    Error, Identity, Kademlia, KademliaConfig, Key, KeySpace, Message, Node, Response, RpcMessage,
+};
+use std::{
+    net::UdpSocket,
+    thread,
+    time::{Duration, Instant},
+};
+
+const NODE_COUNT: usize = 16;
+const BASE_PORT: usize = 12000;
+
+#[test]
+fn small_network_config() {
+    let config = |port: usize| {
+        KademliaConfig::builder()
+            .bind_addr(local(port))
+            .k_param(2)
+            .alpha(1)
+            .n_buckets(8)
+            .request_timeout(Duration::from_millis(100))
+            .build()
+    };
+
+    let nodes = local_network(BASE_PORT, NODE_COUNT, config);
+    let (seed_node, nodes) = nodes.split_first().unwrap();
+
+    for node in nodes.iter() {
+        // 8 buckets, 2 nodes each
+        assert!(node.get_all_know_nodes().len() <= 16);
+        assert!(
+            node.lookup_nodes(&Key::new("key".to_owned()))
+                .unwrap()
+                .len()
+                <= 2
+        );
+    }
+
+    let missing = kademlia::Node::new(local(BASE_PORT - 1), Key::new("missing".to_owned()));
+    let start = Instant::now();
+    assert!(matches!(seed_node.ping(missing), Err(Error::Timeout)));
+    assert!(start.elapsed() < Duration::from_millis(500));
+}
+
+#[test]
+fn separate_networks() {
+    let config = |port: usize, network_id: u32| {
+        KademliaConfig::builder()
+            .bind_addr(local(port))
+            .network_id(network_id)
+            .request_timeout(Duration::from_millis(200))
+            .build()
+    };
+
+    let seed_node =
+        Kademlia::with_config(config(BASE_PORT + 100, 1), Identity::generate()).unwrap();
+
+    let mut member =
+        Kademlia::with_config(config(BASE_PORT + 101, 1), Identity::generate()).unwrap();
+    member.bootstrap(*seed_node.node()).unwrap();
+
+    // message of other network is refused at once instead of timing out
+    let mut stranger =
+        Kademlia::with_config(config(BASE_PORT + 102, 2), Identity::generate()).unwrap();
+    assert!(matches!(
+        stranger.bootstrap(*seed_node.node()),
+        Err(Error::Incompatible(1, 1))
+    ));
+    assert!(seed_node
+        .get_all_know_nodes()
+        .iter()
+        .all(|node| node.id != stranger.node().id));
+}
+
+#[test]
+fn incompatible_peer_is_evicted() {
+    let config = KademliaConfig::builder()
+        .bind_addr(local(BASE_PORT + 110))
+        .request_timeout(Duration::from_millis(200))
+        .build();
+    let node = Kademlia::with_config(config, Identity::generate()).unwrap();
+
+    let socket = UdpSocket::bind(local(BASE_PORT + 111)).unwrap();
+    let identity = Identity::generate();
+    let peer = Node::new(local(BASE_PORT + 111), identity.id());
+    let knows_peer = || {
+        node.get_all_know_nodes()
+            .iter()
+            .any(|known| known.id == peer.id)
+    };
+
+    // peer answers like any other first, then runs on other network
+    let responder = thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        let (len, from) = socket.recv_from(&mut buf).unwrap();
+        let request = BincodeCodec::default().decode(&buf[..len], from).unwrap();
+        let message = Message::Response(Response::Pong);
+        let response = RpcMessage::signed(request.token, peer, message, &identity).unwrap();
+        let response = BincodeCodec::default().encode(&response).unwrap();
+        socket.send_to(&response, from).unwrap();
+
+        let codec = BincodeCodec::new(1);
+        let (len, from) = socket.recv_from(&mut buf).unwrap();
+        let err = codec.decode(&buf[..len], from).err().unwrap();
+        let rejection = codec.reject(&buf[..len], &err).unwrap();
+        socket.send_to(&rejection, from).unwrap();
+    });
+
+    node.ping(peer).unwrap();
+    assert!(knows_peer());
+
+    // peer can't answer any request, it would only take a bucket slot
+    assert!(matches!(node.ping(peer), Err(Error::Incompatible(1, 1))));
+    assert!(!knows_peer(), "Incompatible peer is evicted");
+    responder.join().unwrap();
+}
+
+#[test]
+fn responses_fit_mtu() {
+    let config = |port: usize| {
+        KademliaConfig::builder()
+            .bind_addr(local(port))
+            .k_param(32)
+            .build()
+    };
+
+    let nodes = local_network(BASE_PORT + 200, 30, config);
+    let (seed_node, nodes) = nodes.split_first().unwrap();
+    assert_eq!(seed_node.get_all_know_nodes().len(), 31);
+
+    // 31 nodes don't fit in 1232 bytes, the farthest ones are left out
+    let target = Key::new("target".to_owned());
+    let found = nodes[0].find_node(*seed_node.node(), target).unwrap();
+    assert!(!found.is_empty() && found.len() < 31, "{}", found.len());
+
+    let distances = found.iter().map(|node| node.id.distance(&target));
+    assert!(distances.collect::<Vec<_>>().is_sorted());
+}
+
+#[test]
+fn mainline_key_space() {
+    let config = |port: usize| {
+        KademliaConfig::builder()
+            .bind_addr(local(port))
+            .key_space(KeySpace::Mainline)
+            .request_timeout(Duration::from_millis(200))
+            .build()
+    };
+    assert_eq!(config(BASE_PORT + 300).n_buckets(), 160);
+
+    let nodes = local_network(BASE_PORT + 300, 4, config);
+
+    // 160-bit ids leave the last 12 bytes zero
+    for node in nodes.iter() {
+        assert!(node.node().id.to_string().ends_with(&"0".repeat(24)));
+    }
+
+    // keys are cut to 160 bits, the same value is found under the full key
+    let key = Key::new("key".to_owned());
+    nodes[1].put(key, b"value".to_vec()).unwrap();
+    assert_eq!(nodes[4].get(&key).unwrap(), Some(b"value".to_vec()));
+
+    // node in the native key space doesn't get answers
+    let native = Kademlia::with_config(
+        KademliaConfig::builder()
+            .bind_addr(local(BASE_PORT + 310))
+            .request_timeout(Duration::from_millis(200))
+            .build(),
+        Identity::generate(),
+    )
+    .unwrap();
+    assert!(matches!(native.ping(*nodes[0].node()), Err(Error::Timeout)));
+}
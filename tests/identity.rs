@@ -0,0 +1,73 @@
+mod common;
+
+use common::local;
+use kademlia::{Error, Identity, Kademlia, Key};
+use std::{env, fs, thread};
+
+#[test]
+fn identity_round_trip() {
+    let path = env::temp_dir().join(format!("kademlia-identity-{}.key", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let identity = Identity::load_or_generate(&path).unwrap();
+    let loaded = Identity::load(&path).unwrap();
+    assert_eq!(identity.id(), loaded.id());
+    assert_eq!(identity.id(), Key::from_public_key(&identity.public_key()));
+
+    fs::write(&path, b"short").unwrap();
+    assert!(matches!(Identity::load(&path), Err(Error::InvalidKey)));
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn files_with_same_stem() {
+    let dir = env::temp_dir().join(format!("kademlia-files-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let (key_path, state_path) = (dir.join("node.key"), dir.join("node.state"));
+
+    // concurrent writes of files that differ only in extension keep their own contents
+    let (first, second) = (Identity::generate(), Identity::generate());
+    let writers = [
+        (first.clone(), key_path.clone()),
+        (second.clone(), state_path.clone()),
+    ]
+    .map(|(identity, path)| {
+        thread::spawn(move || {
+            for _ in 0..100 {
+                identity.save(&path).unwrap();
+            }
+        })
+    });
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    assert_eq!(Identity::load(&key_path).unwrap().id(), first.id());
+    assert_eq!(Identity::load(&state_path).unwrap().id(), second.id());
+    assert_eq!(
+        fs::read_dir(&dir).unwrap().count(),
+        2,
+        "No temporary file is left"
+    );
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(&key_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn node_id_bound_to_identity() {
+    let identity = Identity::generate();
+    let id = identity.id();
+    let node = Kademlia::new(local(10140), identity).unwrap();
+
+    assert_eq!(node.node().id, id);
+    assert_eq!(id.to_string().parse::<Key>().unwrap(), id);
+    assert!(matches!("zz".parse::<Key>(), Err(Error::InvalidKey)));
+}
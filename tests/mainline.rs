@@ -0,0 +1,129 @@
+#![cfg(feature = "mainline")]
+
+mod common;
+
+use common::{local, network};
+use kademlia::{
+    codec::Bep5Codec, transport::UdpTransport, Error, Identity, Kademlia, KademliaConfig, Key,
+    KeySpace, Node,
+};
+use std::{net::UdpSocket, thread, time::Duration};
+
+const STAND_IN_ID: &[u8; 20] = b"abcdefghij0123456789";
+
+#[test]
+fn stand_in_mainline_node() {
+    let node = mainline_node(14000);
+    let stand_in = UdpSocket::bind(local(14001)).unwrap();
+    stand_in
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .unwrap();
+    let mut buf = [0u8; 4096];
+
+    // ping from BEP 5
+    stand_in
+        .send_to(
+            b"d1:ad2:id20:abcdefghij0123456789e1:q4:ping1:t2:aa1:y1:qe",
+            node.node().addr,
+        )
+        .unwrap();
+    let (len, _) = stand_in.recv_from(&mut buf).unwrap();
+    let pong = &buf[..len];
+    assert!(pong.starts_with(b"d1:rd2:id20:"));
+    assert!(pong.ends_with(b"e1:t2:aa1:y1:re"));
+    assert_eq!(&pong[12..32], &id_bytes(&node.node().id)[..20]);
+
+    let stand_in_node = Node::new(local(14001), key(STAND_IN_ID));
+    assert!(node.get_all_know_nodes().contains(&stand_in_node));
+
+    // ping to stand-in, answered with the transaction id it was sent with
+    let pinger = node.clone();
+    let ping = thread::spawn(move || pinger.ping(stand_in_node));
+
+    let (len, from) = stand_in.recv_from(&mut buf).unwrap();
+    let query = &buf[..len];
+    assert!(contains(query, b"1:q4:ping"));
+    let start = find(query, b"1:t16:").unwrap() + 6;
+    let transaction = query[start..start + 16].to_vec();
+
+    let mut pong = b"d1:rd2:id20:abcdefghij0123456789e1:t16:".to_vec();
+    pong.extend(transaction);
+    pong.extend(b"1:y1:re");
+    stand_in.send_to(&pong, from).unwrap();
+    assert!(ping.join().unwrap().is_ok());
+
+    // announce without write token from get_peers is refused
+    stand_in
+        .send_to(
+            b"d1:ad2:id20:abcdefghij012345678912:implied_porti1e9:info_hash20:mnopqrstuvwxyz1234564:porti6881e5:token3:bade1:q13:announce_peer1:t2:bb1:y1:qe",
+            node.node().addr,
+        )
+        .unwrap();
+    let (len, _) = stand_in.recv_from(&mut buf).unwrap();
+    assert!(contains(&buf[..len], b"1:eli203e"));
+
+    // error from stand-in completes the announce instead of timing out
+    let announcer = node.clone();
+    let info_hash = key(b"mnopqrstuvwxyz123456");
+    let announce =
+        thread::spawn(move || announcer.add_provider(stand_in_node, info_hash, b"bad".to_vec()));
+
+    let (len, from) = stand_in.recv_from(&mut buf).unwrap();
+    let query = &buf[..len];
+    assert!(contains(query, b"1:q13:announce_peer"));
+    let start = find(query, b"1:t16:").unwrap() + 6;
+    let transaction = query[start..start + 16].to_vec();
+
+    let mut error = b"d1:eli203e9:Bad tokene1:t16:".to_vec();
+    error.extend(transaction);
+    error.extend(b"1:y1:ee");
+    stand_in.send_to(&error, from).unwrap();
+    let announced = announce.join().unwrap();
+    assert!(matches!(announced, Err(Error::Rejected(reason)) if reason == "Bad token"));
+}
+
+#[test]
+fn mainline_providers() {
+    let nodes = network(3, |index| mainline_node(14010 + index));
+
+    let info_hash = Key::new("info hash".to_owned());
+    assert!(nodes[1].start_providing(info_hash).unwrap() > 0);
+
+    let providers = nodes[3].get_providers(&info_hash).unwrap();
+    let addrs = providers.iter().map(|provider| provider.addr);
+    assert_eq!(addrs.collect::<Vec<_>>(), vec![nodes[1].node().addr]);
+}
+
+fn mainline_node(port: usize) -> Kademlia {
+    let config = KademliaConfig::builder()
+        .bind_addr(local(port))
+        .allow_unsigned(true)
+        .key_space(KeySpace::Mainline)
+        .build();
+    let transport = UdpTransport::bind_with_codec(local(port), 4096, Bep5Codec::new()).unwrap();
+
+    Kademlia::with_transport(config, Identity::generate(), transport).unwrap()
+}
+
+fn key(bytes: &[u8; 20]) -> Key {
+    let hex: String = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+    hex.parse().unwrap()
+}
+
+fn id_bytes(id: &Key) -> Vec<u8> {
+    let hex = id.to_string();
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    find(haystack, needle).is_some()
+}
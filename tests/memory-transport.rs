@@ -0,0 +1,276 @@
+mod common;
+
+use kademlia::{
+    transport::{MemoryNetwork, MemoryTransport, Transport},
+    FindValueResult, Identity, Kademlia, KademliaConfig, Key, Message, Node, Request, Result,
+    RpcMessage,
+};
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+const NODE_COUNT: usize = 100;
+
+fn config() -> KademliaConfig {
+    KademliaConfig::builder()
+        .k_param(8)
+        .request_timeout(Duration::from_millis(200))
+        .build()
+}
+
+fn node(network: &MemoryNetwork, index: usize) -> Kademlia {
+    node_with_config(network, index, config())
+}
+
+fn node_with_config(network: &MemoryNetwork, index: usize, config: KademliaConfig) -> Kademlia {
+    let transport = network.bind(addr(index)).unwrap();
+    Kademlia::with_transport(config, Identity::generate(), transport).unwrap()
+}
+
+#[test]
+fn memory_network_lookup() {
+    let network = MemoryNetwork::new().latency(Duration::from_millis(1));
+
+    let nodes = common::network(NODE_COUNT, |index| node(&network, index));
+    let nodes = &nodes[1..];
+
+    let target = nodes[NODE_COUNT / 2].node().id;
+    let found = nodes[0].lookup_nodes(&target).unwrap();
+    assert_eq!(found[0].node.id, target);
+    assert_eq!(found.len(), 8);
+
+    // own id is looked up among other nodes only
+    let own_id = nodes[0].node().id;
+    let found = nodes[0].lookup_nodes(&own_id).unwrap();
+    assert!(found.iter().all(|entry| entry.node.id != own_id));
+
+    // lookup stops once the closest nodes responded instead of crawling the network
+    let stats = nodes[1].lookup(&target).unwrap().stats;
+    assert!(stats.rounds > 0);
+    assert!(stats.rpcs_sent < NODE_COUNT / 2, "{:?}", stats);
+    assert_eq!(stats.failures, 0);
+
+    let key = Key::new("value".to_owned());
+    assert!(nodes[1].put(key, b"value".to_vec()).unwrap() > 0);
+    assert_eq!(nodes[2].get(&key).unwrap(), Some(b"value".to_vec()));
+}
+
+#[test]
+fn memory_network_disjoint_lookup() {
+    let network = MemoryNetwork::new();
+    let config = KademliaConfig::builder()
+        .k_param(8)
+        .disjoint_paths(3)
+        .request_timeout(Duration::from_millis(200))
+        .build();
+
+    let nodes = common::network(NODE_COUNT, |index| {
+        node_with_config(&network, index, config)
+    });
+    let nodes = &nodes[1..];
+
+    let target = nodes[NODE_COUNT / 2].node().id;
+    let found = nodes[0].lookup(&target).unwrap().nodes;
+    assert_eq!(found[0].node.id, target);
+    assert_eq!(found.len(), 8);
+    assert!(found.iter().all(|entry| entry.path < 3));
+
+    let unique = found
+        .iter()
+        .map(|entry| entry.node.id)
+        .collect::<HashSet<_>>();
+    assert_eq!(unique.len(), found.len(), "Every node is found on one path");
+}
+
+/// Stops sending once silenced, so the node still receives requests but never answers
+struct Silenceable {
+    inner: MemoryTransport,
+    silent: Arc<AtomicBool>,
+}
+
+impl Transport for Silenceable {
+    fn send(&self, msg: &RpcMessage, destination: SocketAddr) -> Result<()> {
+        if self.silent.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        self.inner.send(msg, destination)
+    }
+
+    fn recv(&self) -> Result<(RpcMessage, SocketAddr)> {
+        self.inner.recv()
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+}
+
+#[test]
+fn memory_network_failed_lookup_node() {
+    let network = MemoryNetwork::new();
+    let config = KademliaConfig::builder()
+        .k_param(4)
+        .request_timeout(Duration::from_millis(200))
+        .build();
+
+    let mut nodes = vec![];
+    let mut silenced = vec![];
+    for index in 0..30 {
+        let silent = Arc::new(AtomicBool::new(false));
+        let transport = Silenceable {
+            inner: network.bind(addr(index)).unwrap(),
+            silent: silent.clone(),
+        };
+        let kademlia = Kademlia::with_transport(config, Identity::generate(), transport).unwrap();
+        nodes.push(kademlia);
+        silenced.push(silent);
+    }
+
+    for node in &nodes {
+        for other in nodes.iter().filter(|other| other.node() != node.node()) {
+            node.ping(*other.node()).unwrap();
+        }
+    }
+
+    // one of the k closest stops answering after the others learned about it
+    let target = Key::new("target".to_owned());
+    let closest = nodes[0].lookup_nodes(&target).unwrap();
+    assert_eq!(closest.len(), 4);
+    let dead = closest[1].node;
+    let index = nodes.iter().position(|node| *node.node() == dead).unwrap();
+    silenced[index].store(true, Ordering::Relaxed);
+
+    let searcher = nodes.iter().find(|node| *node.node() != dead).unwrap();
+    let result = searcher.lookup(&target).unwrap();
+    assert!(result.stats.failures > 0, "{:?}", result.stats);
+    assert_eq!(
+        result.nodes.len(),
+        4,
+        "Next closest node replaces failed one"
+    );
+    assert!(result.nodes.iter().all(|entry| entry.node != dead));
+}
+
+#[test]
+fn memory_network_caching() {
+    let network = MemoryNetwork::new();
+    let record_ttl = Duration::from_secs(4);
+    let config = KademliaConfig::builder()
+        .k_param(4)
+        .request_timeout(Duration::from_secs(1))
+        .record_ttl(record_ttl)
+        .build();
+
+    let nodes = common::network(30, |index| node_with_config(&network, index, config));
+    let (seed_node, nodes) = nodes.split_first().unwrap();
+
+    // bootstrap only fills buckets near own id, lookups from nodes that know no one
+    // close to key would miss the value
+    for node in nodes {
+        for other in nodes.iter().filter(|other| other.node() != node.node()) {
+            node.ping(*other.node()).unwrap();
+        }
+    }
+
+    let key = Key::new("cached".to_owned());
+    let holders = |nodes: &[Kademlia]| {
+        nodes
+            .iter()
+            .filter(|holder| {
+                let value = seed_node.find_value(*holder.node(), key).unwrap();
+                matches!(value, FindValueResult::Value(_))
+            })
+            .count()
+    };
+
+    // closest node is left without the value, so it responds on the lookup path
+    let stored_at = Instant::now();
+    let closest = seed_node.lookup_nodes(&key).unwrap();
+    for entry in &closest[1..] {
+        let value = b"value".to_vec();
+        seed_node.store(entry.node, key, value, record_ttl).unwrap();
+    }
+    let stored = holders(nodes);
+    assert_eq!(stored, closest.len() - 1);
+
+    for node in nodes {
+        assert_eq!(node.get(&key).unwrap(), Some(b"value".to_vec()));
+    }
+
+    // caching is done in background
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while holders(nodes) == stored && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    assert!(
+        holders(nodes) > stored,
+        "Value is cached on the lookup path"
+    );
+
+    // cached copies live at most half of record ttl, records are still held
+    // until the whole ttl passed
+    while holders(nodes) != stored && stored_at.elapsed() < record_ttl {
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!(holders(nodes), stored, "Cached copies expired first");
+}
+
+#[test]
+fn memory_network_packet_loss() {
+    let network = MemoryNetwork::with_seed(7).packet_loss(1.0);
+
+    let first = node(&network, 0);
+    let second = node(&network, 1);
+    assert!(first.ping(*second.node()).is_err(), "All packets are lost");
+
+    assert!(
+        network.bind(addr(0)).is_err(),
+        "Address is already used by first node"
+    );
+
+    let unused = network.bind(addr(2)).unwrap();
+    assert_eq!(unused.local_addr().unwrap(), addr(2));
+}
+
+#[test]
+fn memory_network_partial_packet_loss() {
+    let delivered = |seed: u64| {
+        let network = MemoryNetwork::with_seed(seed).packet_loss(0.5);
+        let sender = network.bind(addr(0)).unwrap();
+        let receiver = network.bind(addr(1)).unwrap();
+
+        let source = Node::new(addr(0), Identity::generate().id());
+        let ping = |token| RpcMessage::unsigned(token, source, Message::Request(Request::Ping));
+        for token in 0..100 {
+            sender.send(&ping(token), addr(1)).unwrap();
+        }
+
+        // network is shared, last message always arrives and ends receiving
+        let _ = network.clone().packet_loss(0.0);
+        sender.send(&ping(u128::MAX), addr(1)).unwrap();
+
+        let mut tokens = vec![];
+        loop {
+            let (msg, _) = receiver.recv().unwrap();
+            if msg.token == u128::MAX {
+                return tokens;
+            }
+            tokens.push(msg.token);
+        }
+    };
+
+    let first = delivered(7);
+    assert!(first.len() > 20 && first.len() < 80, "{}", first.len());
+    assert_eq!(delivered(7), first, "Same seed loses the same packets");
+    assert_ne!(delivered(8), first);
+}
+
+fn addr(index: usize) -> SocketAddr {
+    SocketAddr::from(([10, 0, (index / 256) as u8, (index % 256) as u8], 4000))
+}
@@ -1,20 +1,33 @@
 #![allow(unused)]
 
-use kademlia::{Kademlia, Key, Node};
+mod common;
+
+use common::local;
+use kademlia::{AddressConfig, Identity, Kademlia, KademliaConfig, Key, Node};
 use log::{error, info};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
 
 const NODE_COUNT: usize = 64;
 
+/// Buckets large enough to never fill, ids are random so otherwise
+/// new node could end up only in seeds replacement cache.
+/// Responses with that many nodes need bigger datagrams
+fn unbounded(port: usize) -> KademliaConfig {
+    KademliaConfig::builder()
+        .bind_addr(local(port))
+        .k_param(NODE_COUNT + 2)
+        .max_datagram_size(16 * 1024)
+        .mtu(16 * 1024)
+        .build()
+}
+
 #[test]
 fn node_finding() {
     env_logger::init();
     let mut nodes = Vec::with_capacity(NODE_COUNT);
 
     for node in 0..NODE_COUNT {
-        nodes.push(Kademlia::new(
-            (10000 + node) as u16,
-            Key::new((10000 + node).to_string()),
-        ));
+        nodes.push(Kademlia::with_config(unbounded(10000 + node), Identity::generate()).unwrap());
     }
 
     println!("Created {} nodes", NODE_COUNT);
@@ -32,20 +45,16 @@ fn node_finding() {
         );
     }
 
-    let seed_node = Kademlia::new(
-        (10000 + NODE_COUNT) as u16,
-        Key::new((10000 + NODE_COUNT).to_string()),
-    );
+    let seed_node =
+        Kademlia::with_config(unbounded(10000 + NODE_COUNT), Identity::generate()).unwrap();
 
     for (_pos, node) in nodes.iter_mut().enumerate() {
-        assert!(seed_node.ping(node.node().clone()));
+        assert!(seed_node.ping(node.node().clone()).is_ok());
         assert_eq!(node.get_all_know_nodes().len(), 2);
     }
 
-    let mut new_node = Kademlia::new(
-        (10000 + NODE_COUNT + 1) as u16,
-        Key::new((10000 + NODE_COUNT + 1).to_string()),
-    );
+    let mut new_node =
+        Kademlia::with_config(unbounded(10000 + NODE_COUNT + 1), Identity::generate()).unwrap();
     let new_node_id = new_node.node().id;
     dbg!(seed_node.get_all_know_nodes().len());
 
@@ -54,7 +63,7 @@ fn node_finding() {
         1,
         "There should be only one know node, itself"
     );
-    new_node.ping(seed_node.node().clone());
+    new_node.ping(seed_node.node().clone()).unwrap();
     assert_eq!(
         new_node.get_all_know_nodes().len(),
         2,
@@ -63,8 +72,8 @@ fn node_finding() {
 
     for (_pos, node) in nodes.iter_mut().enumerate() {
         let f = node.find_node(*seed_node.node(), new_node_id);
-        assert!(f.is_some(), "Node should be able to find any node");
-        node.bootstrap(*seed_node.node());
+        assert!(f.is_ok(), "Node should be able to find any node");
+        node.bootstrap(*seed_node.node()).unwrap();
     }
 
     dbg!(new_node.get_all_know_nodes().len());
@@ -75,7 +84,57 @@ fn node_finding() {
         "At least 1 node should have connected to node in process"
     );
 
-    let response = new_node.ping(Node::new(9999, Key::new(9999.to_string())));
-    assert!(!response, "This node should not exist");
+    let response = new_node.ping(Node::new(local(9999), Key::new(9999.to_string())));
+    assert!(response.is_err(), "This node should not exist");
     // panic!("Panic to see debugs")
 }
+
+#[test]
+fn ipv6_node_finding() {
+    let addr = |port: u16| SocketAddr::from((Ipv6Addr::LOCALHOST, port));
+
+    let seed_node = Kademlia::new(addr(10100), Identity::generate()).unwrap();
+    let mut node = Kademlia::new(addr(10101), Identity::generate()).unwrap();
+
+    node.bootstrap(*seed_node.node()).unwrap();
+    assert_eq!(seed_node.get_all_know_nodes().len(), 2);
+    assert!(seed_node.ping(*node.node()).is_ok());
+}
+
+#[test]
+fn unspecified_bind_address() {
+    let any = |port: u16| SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
+
+    let config = KademliaConfig::builder().bind_addr(any(10110)).build();
+    let node = Kademlia::with_config(config, Identity::generate()).unwrap();
+    let peer = Kademlia::new(local(10111), Identity::generate()).unwrap();
+    assert!(node.ping(*peer.node()).is_ok());
+
+    let known = peer.get_all_know_nodes();
+    let node_entry = known.iter().find(|n| n.id == node.node().id).unwrap();
+    assert_eq!(
+        node_entry.addr,
+        local(10110),
+        "Peer should see address the request came from"
+    );
+
+    let external = AddressConfig::new(any(10112)).with_external(local(10112));
+    let config = KademliaConfig::builder().addresses(external).build();
+    let node = Kademlia::with_config(config, Identity::generate()).unwrap();
+    assert_eq!(node.node().addr, local(10112));
+    assert!(peer.ping(*node.node()).is_ok());
+}
+
+#[test]
+fn malformed_datagram() {
+    let node = Kademlia::new(local(10120), Identity::generate()).unwrap();
+    let peer = Kademlia::new(local(10121), Identity::generate()).unwrap();
+
+    let socket = UdpSocket::bind(local(10122)).unwrap();
+    socket.send_to(&[0xFF; 16], node.node().addr).unwrap();
+
+    assert!(
+        peer.ping(*node.node()).is_ok(),
+        "Malformed message should be dropped without stopping the node"
+    );
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use common::local;
+use kademlia::{Error, Identity, Kademlia, KademliaConfig};
+
+const BASE_PORT: usize = 10150;
+
+fn config(port: usize) -> KademliaConfig {
+    KademliaConfig::builder()
+        .bind_addr(local(port))
+        .puzzle_difficulty(4, 4)
+        .build()
+}
+
+#[test]
+fn puzzle_admission() {
+    let seed_node =
+        Kademlia::with_config(config(BASE_PORT), Identity::generate_with_puzzle(4)).unwrap();
+    let mut node =
+        Kademlia::with_config(config(BASE_PORT + 1), Identity::generate_with_puzzle(4)).unwrap();
+
+    node.bootstrap(*seed_node.node()).unwrap();
+    assert_eq!(seed_node.get_all_know_nodes().len(), 2);
+    assert_eq!(node.get_all_know_nodes().len(), 2);
+
+    // node with cheap id is answered but not admitted
+    let cheap = unsolved(&config(BASE_PORT));
+    let cheap_id = cheap.id();
+    let cheap_node = Kademlia::new(local(BASE_PORT + 2), cheap).unwrap();
+    assert!(cheap_node.ping(*seed_node.node()).is_ok());
+    assert!(seed_node
+        .get_all_know_nodes()
+        .iter()
+        .all(|known| known.id != cheap_id));
+}
+
+#[test]
+fn unsolved_identity_is_refused() {
+    let config = config(BASE_PORT + 3);
+    let result = Kademlia::with_config(config, unsolved(&config));
+    assert!(matches!(result, Err(Error::PuzzleNotSolved)));
+}
+
+/// Identity whose id doesn't solve static puzzle
+fn unsolved(config: &KademliaConfig) -> Identity {
+    let difficulty = config.puzzle_difficulty();
+    std::iter::repeat_with(Identity::generate)
+        .find(|identity| !difficulty.is_static_solved(&identity.id()))
+        .unwrap()
+}
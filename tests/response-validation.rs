@@ -0,0 +1,128 @@
+mod common;
+
+use common::local;
+use kademlia::{
+    codec::{BincodeCodec, Codec},
+    Identity, Kademlia, Message, Node, Request, Response, RpcMessage,
+};
+use std::{net::UdpSocket, thread, time::Duration};
+
+#[test]
+fn spoofed_responses_are_rejected() {
+    let node = Kademlia::new(local(10130), Identity::generate()).unwrap();
+
+    let peer_socket = UdpSocket::bind(local(10131)).unwrap();
+    let spoofer_socket = UdpSocket::bind(local(10132)).unwrap();
+    let peer_identity = Identity::generate();
+    let peer = Node::new(local(10131), peer_identity.id());
+
+    let pinger = node.clone();
+    let ping = thread::spawn(move || pinger.ping(peer));
+
+    let mut buf = [0u8; 4096];
+    let (len, from) = peer_socket.recv_from(&mut buf).unwrap();
+    let request = BincodeCodec::default().decode(&buf[..len], from).unwrap();
+    assert!(request.verify().is_ok());
+
+    let response = |source: Node, identity: &Identity| {
+        RpcMessage::signed(
+            request.token,
+            source,
+            Message::Response(Response::Pong),
+            identity,
+        )
+        .unwrap()
+    };
+
+    // right node id, wrong address
+    let spoofed = encode(&response(peer, &peer_identity));
+    spoofer_socket.send_to(&spoofed, from).unwrap();
+
+    // right address, wrong node id
+    let impostor_identity = Identity::generate();
+    let impostor = Node::new(peer.addr, impostor_identity.id());
+    let spoofed = encode(&response(impostor, &impostor_identity));
+    peer_socket.send_to(&spoofed, from).unwrap();
+
+    // claims peer id but is signed with another key
+    let spoofed = encode(&response(peer, &impostor_identity));
+    peer_socket.send_to(&spoofed, from).unwrap();
+
+    // signature doesn't cover the message
+    let mut tampered = response(peer, &peer_identity);
+    tampered.signature = Some(peer_identity.sign(b"something else"));
+    peer_socket.send_to(&encode(&tampered), from).unwrap();
+
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(node.rejected_responses(), 4);
+
+    let real = encode(&response(peer, &peer_identity));
+    peer_socket.send_to(&real, from).unwrap();
+    assert!(
+        ping.join().unwrap().is_ok(),
+        "Real response is still accepted"
+    );
+}
+
+#[test]
+fn unsigned_requests_are_ignored() {
+    let node = Kademlia::new(local(10133), Identity::generate()).unwrap();
+    let socket = UdpSocket::bind(local(10134)).unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+
+    let identity = Identity::generate();
+    let claimed = Node::new(local(10134), Identity::generate().id());
+    let request =
+        RpcMessage::signed(1, claimed, Message::Request(Request::Ping), &identity).unwrap();
+    socket.send_to(&encode(&request), node.node().addr).unwrap();
+
+    let unsigned = RpcMessage::unsigned(2, claimed, Message::Request(Request::Ping));
+    socket
+        .send_to(&encode(&unsigned), node.node().addr)
+        .unwrap();
+
+    let mut buf = [0u8; 4096];
+    assert!(socket.recv_from(&mut buf).is_err(), "No response is sent");
+    assert!(node
+        .get_all_know_nodes()
+        .iter()
+        .all(|known| known.id != claimed.id));
+}
+
+#[test]
+fn responses_go_to_sender() {
+    let node = Kademlia::new(local(10135), Identity::generate()).unwrap();
+    let sender = UdpSocket::bind(local(10136)).unwrap();
+    let victim = UdpSocket::bind(local(10137)).unwrap();
+    for socket in [&sender, &victim] {
+        socket
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+    }
+
+    // claims the address of another host
+    let identity = Identity::generate();
+    let claimed = Node::new(local(10137), identity.id());
+    let request =
+        RpcMessage::signed(1, claimed, Message::Request(Request::Ping), &identity).unwrap();
+    sender.send_to(&encode(&request), node.node().addr).unwrap();
+
+    let mut buf = [0u8; 4096];
+    let (len, from) = sender.recv_from(&mut buf).unwrap();
+    let response = BincodeCodec::default().decode(&buf[..len], from).unwrap();
+    assert_eq!(response.token, 1);
+    assert!(matches!(
+        response.message,
+        Message::Response(Response::Pong)
+    ));
+    assert!(
+        victim.recv_from(&mut buf).is_err(),
+        "Claimed address gets nothing"
+    );
+}
+
+fn encode(msg: &RpcMessage) -> Vec<u8> {
+    BincodeCodec::default().encode(msg).unwrap()
+}
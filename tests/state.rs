@@ -0,0 +1,52 @@
+mod common;
+
+use common::local;
+use kademlia::{
+    codec::{BincodeCodec, Codec},
+    Identity, Kademlia, KademliaConfig, Message, Node, Response, RpcMessage,
+};
+use std::{env, fs, net::UdpSocket, thread};
+
+#[test]
+fn save_and_load_state() {
+    let path = env::temp_dir().join(format!("kademlia-state-{}.state", std::process::id()));
+
+    let node = Kademlia::new(local(10160), Identity::generate()).unwrap();
+    let peer = Kademlia::new(local(10161), Identity::generate()).unwrap();
+    node.ping(*peer.node()).unwrap();
+
+    // peer that answers once and is gone when state is restored
+    let gone = {
+        let socket = UdpSocket::bind(local(10162)).unwrap();
+        let identity = Identity::generate();
+        let gone = Node::new(local(10162), identity.id());
+
+        let responder = thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            let (len, from) = socket.recv_from(&mut buf).unwrap();
+            let request = BincodeCodec::default().decode(&buf[..len], from).unwrap();
+            let message = Message::Response(Response::Pong);
+            let response = RpcMessage::signed(request.token, gone, message, &identity).unwrap();
+            let response = BincodeCodec::default().encode(&response).unwrap();
+            socket.send_to(&response, from).unwrap();
+        });
+
+        node.ping(gone).unwrap();
+        responder.join().unwrap();
+        gone
+    };
+    assert_eq!(node.get_all_know_nodes().len(), 3);
+
+    node.save_state(&path).unwrap();
+
+    let config = KademliaConfig::builder().bind_addr(local(10163)).build();
+    let restored = Kademlia::load_state(&path, config).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(restored.node().id, node.node().id);
+
+    let known = restored.get_all_know_nodes();
+    assert_eq!(known.len(), 2, "Only responding peer is restored");
+    assert!(known.iter().any(|known| known.id == peer.node().id));
+    assert!(known.iter().all(|known| known.id != gone.id));
+}
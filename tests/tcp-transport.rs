@@ -0,0 +1,164 @@
+mod common;
+
+use common::{local, local_network};
+use kademlia::{
+    codec::{BincodeCodec, Codec},
+    transport::{HybridTransport, TcpTransport, Transport},
+    Error, Identity, KademliaConfig, Key, Message, Node, Request, Response, RpcMessage,
+};
+use std::{
+    io::Write,
+    net::{TcpStream, UdpSocket},
+    time::Duration,
+};
+
+#[test]
+fn pooled_connections() {
+    let first = TcpTransport::bind(local(15000)).unwrap();
+    let second = TcpTransport::bind(local(15001)).unwrap();
+
+    let identity = Identity::generate();
+    let source = Node::new(local(15000), identity.id());
+    let ping = |token| {
+        RpcMessage::signed(token, source, Message::Request(Request::Ping), &identity).unwrap()
+    };
+
+    first.send(&ping(1), local(15001)).unwrap();
+    let (received, from) = second.recv().unwrap();
+    assert_eq!(received.token, 1);
+    assert_eq!(
+        from,
+        local(15000),
+        "Sender is identified by its listening port"
+    );
+    assert!(received.verify().is_ok());
+
+    // answer goes back over the same connection
+    let pong = RpcMessage::unsigned(1, source, Message::Response(Response::Pong));
+    second.send(&pong, from).unwrap();
+    let (received, from) = first.recv().unwrap();
+    assert!(matches!(
+        received.message,
+        Message::Response(Response::Pong)
+    ));
+    assert_eq!(from, local(15001));
+
+    first.send(&ping(2), local(15001)).unwrap();
+    assert_eq!(second.recv().unwrap().0.token, 2);
+    assert_eq!(first.connections(), 1);
+    assert_eq!(second.connections(), 1);
+
+    assert!(
+        first.send(&ping(3), local(15002)).is_err(),
+        "Nothing listens"
+    );
+}
+
+#[test]
+fn claimed_port_is_not_pooled() {
+    let claimed = TcpTransport::bind(local(15030)).unwrap();
+    let transport = TcpTransport::bind(local(15031)).unwrap();
+
+    let identity = Identity::generate();
+    let ping = |token, source| {
+        RpcMessage::signed(token, source, Message::Request(Request::Ping), &identity).unwrap()
+    };
+
+    // other process on the same ip claims the port of a node
+    let mut impostor = TcpStream::connect(local(15031)).unwrap();
+    let write_frame = |stream: &mut TcpStream, payload: &[u8]| {
+        stream.write_all(&(payload.len() as u32).to_be_bytes())?;
+        stream.write_all(payload)
+    };
+    write_frame(&mut impostor, &15030u16.to_be_bytes()).unwrap();
+
+    let elsewhere = Node::new(local(15039), identity.id());
+    let encoded = BincodeCodec::default().encode(&ping(1, elsewhere)).unwrap();
+    write_frame(&mut impostor, &encoded).unwrap();
+
+    let (received, from) = transport.recv().unwrap();
+    assert_eq!(received.token, 1);
+    assert_eq!(from, local(15030));
+    assert_eq!(
+        transport.connections(),
+        0,
+        "Message signed by other node doesn't pool connection"
+    );
+
+    // messages to the claimed address reach the node listening there
+    let source = Node::new(local(15031), identity.id());
+    transport.send(&ping(2, source), local(15030)).unwrap();
+    assert_eq!(claimed.recv().unwrap().0.token, 2);
+    assert_eq!(transport.connections(), 1);
+}
+
+#[test]
+fn large_values_over_tcp() {
+    let config = |port: usize| {
+        KademliaConfig::builder()
+            .bind_addr(local(port))
+            .tcp(true)
+            .request_timeout(Duration::from_millis(500))
+            .build()
+    };
+
+    let nodes = local_network(15010, 3, config);
+
+    // too long for UDP fragments
+    let key = Key::new("large".to_owned());
+    let value = (0..=255).cycle().take(200_000).collect::<Vec<u8>>();
+    assert!(nodes[1].put(key, value.clone()).unwrap() > 0);
+    assert_eq!(nodes[3].get(&key).unwrap(), Some(value));
+}
+
+#[test]
+fn transport_selected_by_size() {
+    let first = HybridTransport::bind(local(15020), 4096).unwrap();
+    let second = HybridTransport::bind(local(15021), 4096).unwrap();
+
+    let identity = Identity::generate();
+    let source = Node::new(local(15020), identity.id());
+    let store = |value: Vec<u8>| {
+        let ttl = Duration::from_secs(60);
+        let request = Request::Store(Key::new("key".to_owned()), value, ttl, false);
+        RpcMessage::signed(1, source, Message::Request(request), &identity).unwrap()
+    };
+
+    first.send(&store(vec![0; 100]), local(15021)).unwrap();
+    assert!(second.recv().is_ok());
+    assert_eq!(first.connections(), 0, "Short message is sent over UDP");
+
+    first.send(&store(vec![0; 100_000]), local(15021)).unwrap();
+    let (received, from) = second.recv().unwrap();
+    assert!(matches!(
+        received.message,
+        Message::Request(Request::Store(_, value, ..)) if value.len() == 100_000
+    ));
+    assert_eq!(from, local(15020));
+    assert_eq!(first.connections(), 1);
+
+    let too_long = first.send(&store(vec![0; 2_000_000]), local(15021));
+    assert!(matches!(too_long, Err(Error::MessageTooLong(_))));
+}
+
+#[test]
+fn configured_mtu() {
+    let mtu = 4000;
+    let codec = BincodeCodec::default();
+    let transport = HybridTransport::bind_with_codec(local(15040), 4096, mtu, codec).unwrap();
+    let peer = UdpSocket::bind(local(15041)).unwrap();
+
+    let identity = Identity::generate();
+    let source = Node::new(local(15040), identity.id());
+    let ttl = Duration::from_secs(60);
+    let request = Request::Store(Key::new("key".to_owned()), vec![0; 3000], ttl, false);
+    let store = RpcMessage::signed(1, source, Message::Request(request), &identity).unwrap();
+
+    // longer than default MTU, still sent in one datagram
+    transport.send(&store, local(15041)).unwrap();
+    let mut buf = [0u8; 4096];
+    let (len, from) = peer.recv_from(&mut buf).unwrap();
+    let received = codec.decode(&buf[..len], from).unwrap();
+    assert_eq!(received.token, 1);
+    assert_eq!(transport.connections(), 0);
+}
@@ -0,0 +1,158 @@
+mod common;
+
+use common::{local, local_network, network};
+use kademlia::{Error, Identity, Kademlia, KademliaConfig, Key};
+use std::{thread, time::Duration};
+
+const NODE_COUNT: usize = 8;
+const BASE_PORT: usize = 11000;
+
+#[test]
+fn store_and_find_value() {
+    let nodes = network(NODE_COUNT, |index| {
+        Kademlia::new(local(BASE_PORT + index), Identity::generate()).unwrap()
+    });
+    let nodes = &nodes[1..];
+
+    let key = Key::new("value-key".to_owned());
+    let value = b"stored value".to_vec();
+
+    let stored = nodes[0].put(key, value.clone()).unwrap();
+    assert!(stored > 0, "Value should be stored on at least one node");
+
+    for node in nodes.iter().skip(1) {
+        assert_eq!(node.get(&key).unwrap(), Some(value.clone()));
+    }
+
+    let missing = Key::new("missing-key".to_owned());
+    assert_eq!(
+        nodes[1].get(&missing).unwrap(),
+        None,
+        "Value was never stored"
+    );
+}
+
+#[test]
+fn values_expire() {
+    let nodes = local_network(BASE_PORT + 100, 3, |port| {
+        KademliaConfig::builder()
+            .bind_addr(local(port))
+            .record_ttl(Duration::from_millis(500))
+            .build()
+    });
+
+    let key = Key::new("expiring-key".to_owned());
+    assert!(nodes[0].put(key, b"value".to_vec()).unwrap() > 0);
+    assert_eq!(nodes[1].get(&key).unwrap(), Some(b"value".to_vec()));
+
+    thread::sleep(Duration::from_secs(1));
+    assert_eq!(nodes[1].get(&key).unwrap(), None);
+}
+
+#[test]
+fn publisher_refreshes_values() {
+    let nodes = local_network(BASE_PORT + 110, 3, |port| {
+        KademliaConfig::builder()
+            .bind_addr(local(port))
+            .record_ttl(Duration::from_millis(500))
+            .publish_interval(Duration::from_millis(200))
+            .build()
+    });
+
+    let key = Key::new("refreshed-key".to_owned());
+    assert!(nodes[0].put(key, b"value".to_vec()).unwrap() > 0);
+
+    thread::sleep(Duration::from_millis(1500));
+    assert_eq!(nodes[1].get(&key).unwrap(), Some(b"value".to_vec()));
+
+    nodes[0].unpublish(&key);
+    thread::sleep(Duration::from_millis(1000));
+    assert_eq!(
+        nodes[1].get(&key).unwrap(),
+        None,
+        "Unpublished value expires"
+    );
+}
+
+#[test]
+fn find_providers() {
+    let nodes = local_network(BASE_PORT + 120, 3, |port| {
+        KademliaConfig::builder().bind_addr(local(port)).build()
+    });
+
+    let key = Key::new("provided-key".to_owned());
+    assert!(nodes[0].start_providing(key).unwrap() > 0);
+    assert!(nodes[1].start_providing(key).unwrap() > 0);
+
+    let mut providers = nodes[3].get_providers(&key).unwrap();
+    providers.sort_by_key(|provider| provider.addr);
+    assert_eq!(providers, vec![*nodes[0].node(), *nodes[1].node()]);
+
+    let missing = Key::new("unprovided-key".to_owned());
+    assert!(nodes[3].get_providers(&missing).unwrap().is_empty());
+}
+
+#[test]
+fn providers_expire() {
+    let nodes = local_network(BASE_PORT + 130, 3, |port| {
+        KademliaConfig::builder()
+            .bind_addr(local(port))
+            .record_ttl(Duration::from_millis(500))
+            .build()
+    });
+
+    let key = Key::new("expiring-provider".to_owned());
+    assert!(nodes[0].start_providing(key).unwrap() > 0);
+    assert_eq!(
+        nodes[2].get_providers(&key).unwrap(),
+        vec![*nodes[0].node()]
+    );
+
+    thread::sleep(Duration::from_secs(1));
+    assert!(nodes[2].get_providers(&key).unwrap().is_empty());
+}
+
+#[test]
+fn large_values_are_fragmented() {
+    let nodes = local_network(BASE_PORT + 140, 3, |port| {
+        KademliaConfig::builder().bind_addr(local(port)).build()
+    });
+
+    let key = Key::new("large".to_owned());
+    let value = (0..=255).cycle().take(20_000).collect::<Vec<u8>>();
+    assert!(nodes[1].put(key, value.clone()).unwrap() > 0);
+    assert_eq!(nodes[3].get(&key).unwrap(), Some(value));
+
+    let too_large = vec![0; 100_000];
+    assert!(matches!(
+        nodes[1].store(*nodes[2].node(), key, too_large, Duration::from_secs(60)),
+        Err(Error::MessageTooLong(_))
+    ));
+}
+
+#[test]
+fn full_store_refuses_values() {
+    let config = |port: usize| {
+        KademliaConfig::builder()
+            .bind_addr(local(port))
+            .max_store_size(1024)
+            .build()
+    };
+    let node = Kademlia::with_config(config(BASE_PORT + 150), Identity::generate()).unwrap();
+    let peer = Kademlia::with_config(config(BASE_PORT + 151), Identity::generate()).unwrap();
+
+    let ttl = Duration::from_secs(60);
+    let key = Key::new("first".to_owned());
+    peer.store(*node.node(), key, vec![0; 512], ttl).unwrap();
+
+    let other = Key::new("second".to_owned());
+    let refused = peer.store(*node.node(), other, vec![0; 512], ttl);
+    assert!(matches!(refused, Err(Error::Rejected(reason)) if reason == "Store is full"));
+    assert!(
+        peer.get_all_know_nodes().contains(node.node()),
+        "Refusing node is not evicted"
+    );
+
+    // replacing held value fits
+    peer.store(*node.node(), key, vec![1; 512], ttl).unwrap();
+}